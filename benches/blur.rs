@@ -0,0 +1,29 @@
+// times the blur fast paths against the full kernel reference on one of the example images
+// run with 'cargo bench', the pixel differences are checked by the tests in filters::blur
+use std::time::Instant;
+use ndarray::Array3;
+use magrustte::imgarray::AsImage;
+use magrustte::filters::Manipulate;
+use magrustte::filters::blur::{Blur, BlurMode};
+
+fn main() {
+    let img = Array3::<u8>::read("assets/anubis.jpg").expect("the example image should be readable");
+    println!("{}x{} image", img.dim().0, img.dim().1);
+
+    // radius 24 takes the iterated box path of the gaussian, the sorted median reference is too slow for it
+    let cases: [(BlurMode, &[i32]); 3] = [(BlurMode::Gaussian, &[2, 8, 24]), (BlurMode::Box, &[2, 8, 24]), (BlurMode::Median, &[2, 8])];
+    for (mode, radii) in cases {
+        for &radius in radii {
+            let mut blur = Blur::new(radius, mode);
+            let start = Instant::now();
+            blur.apply_reference(&img);
+            let reference_time = start.elapsed().as_secs_f64();
+
+            let start = Instant::now();
+            blur.apply(&img);
+            let fast_time = start.elapsed().as_secs_f64();
+            println!("{:<50} reference: {:>7.3}s, fast: {:>7.3}s ({:.1}x)",
+                blur.details_str(), reference_time, fast_time, reference_time / fast_time.max(f64::EPSILON));
+        }
+    }
+}
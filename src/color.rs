@@ -0,0 +1,173 @@
+// on sRGB and CIELAB conversions http://www.brucelindbloom.com/index.html?Math.html
+
+pub type Rgb = [u8; 3];
+
+// D65 reference white
+const WHITE_X: f64 = 0.95047;
+const WHITE_Y: f64 = 1.0;
+const WHITE_Z: f64 = 1.08883;
+
+/// Accepts '#rrggbb', 'rrggbb' and the short '#rgb' form.
+pub fn parse_hex(s: &str) -> Result<Rgb, String> {
+    let hex = s.trim_start_matches('#');
+    // checked up front, the channels are sliced by byte below and from_str_radix would also take a sign
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} is not a valid color", s));
+    }
+    let expanded = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect::<String>(),
+        6 => hex.to_string(),
+        _ => return Err(format!("{} is not a valid color", s)),
+    };
+    let channel = |i: usize| u8::from_str_radix(&expanded[i..i + 2], 16)
+        .map_err(|_| format!("{} is not a valid color", s));
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+pub fn to_hex(color: &Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    let c = c / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn linear_to_srgb(c: f64) -> f64 {
+    let c = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).clamp(0.0, 255.0)
+}
+
+fn lab_f(t: f64) -> f64 {
+    if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 }
+}
+
+fn lab_f_inv(t: f64) -> f64 {
+    if t.powi(3) > 216.0 / 24389.0 { t.powi(3) } else { (116.0 * t - 16.0) * 27.0 / 24389.0 }
+}
+
+/// Returns `[L*, a*, b*]` with L* in 0..100.
+pub fn rgb_to_lab(r: f64, g: f64, b: f64) -> [f64; 3] {
+    let (r, g, b) = (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+    let x = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = 0.0193339 * r + 0.1191920 * g + 0.9503041 * b;
+
+    let (fx, fy, fz) = (lab_f(x / WHITE_X), lab_f(y / WHITE_Y), lab_f(z / WHITE_Z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Returns sRGB values in 0..255, not rounded.
+pub fn lab_to_rgb(lab: &[f64; 3]) -> [f64; 3] {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+    let (x, y, z) = (lab_f_inv(fx) * WHITE_X, lab_f_inv(fy) * WHITE_Y, lab_f_inv(fz) * WHITE_Z);
+
+    let r = 3.2404542 * x - 1.5371385 * y - 0.4985314 * z;
+    let g = -0.9692660 * x + 1.8760108 * y + 0.0415560 * z;
+    let b = 0.0556434 * x - 0.2040259 * y + 1.0572252 * z;
+    [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)]
+}
+
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<Rgb>,
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: Vec<Rgb>) -> Self {
+        Self { name: name.to_string(), colors }
+    }
+
+    pub fn gray(levels: usize) -> Self {
+        let levels = levels.clamp(2, 256);
+        let colors = (0..levels)
+            .map(|i| {
+                let v = (i as f64 * 255.0 / (levels - 1) as f64).round() as u8;
+                [v, v, v]
+            })
+            .collect();
+        Self::new(&format!("gray{}", levels), colors)
+    }
+
+    /// <https://en.wikipedia.org/wiki/Web_colors#Web-safe_colors>
+    pub fn websafe() -> Self {
+        let steps = [0, 51, 102, 153, 204, 255];
+        let mut colors = Vec::<Rgb>::new();
+        for r in steps {
+            for g in steps {
+                for b in steps {
+                    colors.push([r, g, b]);
+                }
+            }
+        }
+        Self::new("websafe", colors)
+    }
+
+    pub fn nearest(&self, color: &[f64; 3]) -> Rgb {
+        let mut best = (self.colors[0], f64::MAX);
+        for c in &self.colors {
+            let (dr, dg, db) = (c[0] as f64 - color[0], c[1] as f64 - color[1], c[2] as f64 - color[2]);
+            let distance = dr * dr + dg * dg + db * db;
+            if distance < best.1 {
+                best = (*c, distance);
+            }
+        }
+        best.0
+    }
+
+    /// Largest gap between neighbouring palette values in any channel, i.e. how far apart palette colors are.
+    pub fn spread(&self) -> f64 {
+        (0..3)
+            .map(|c| {
+                let mut values = self.colors.iter().map(|color| color[c]).collect::<Vec<u8>>();
+                values.sort();
+                values.dedup();
+                values.windows(2).map(|w| (w[1] - w[0]) as f64).fold(0.0, f64::max)
+            })
+            .fold(0.0, f64::max)
+    }
+}
+
+impl std::str::FromStr for Palette {
+    type Err = String;
+
+    // 'bw', 'gray<levels>', 'websafe' or a comma separated list of colors
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bw" => Ok(Palette::gray(2)),
+            "websafe" => Ok(Palette::websafe()),
+            _ if s.starts_with("gray") => match s[4..].parse::<usize>() {
+                Ok(levels) if levels >= 2 => Ok(Palette::gray(levels)),
+                _ => Err(format!("{} is not a valid gray palette", s)),
+            },
+            _ => {
+                let colors = s.split(',').map(parse_hex).collect::<Result<Vec<Rgb>, String>>()?;
+                if colors.len() < 2 {
+                    return Err("palette needs at least 2 colors".to_string());
+                }
+                Ok(Palette::new(s, colors))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_forms() {
+        assert_eq!(parse_hex("#ff8800"), Ok([255, 136, 0]));
+        assert_eq!(parse_hex("ff8800"), Ok([255, 136, 0]));
+        assert_eq!(parse_hex("#f80"), Ok([255, 136, 0]));
+    }
+
+    #[test]
+    fn parse_hex_rejects_bad_input() {
+        for s in ["", "#", "#ff88", "#gg8800", "€", "#€", "€€", "ff€", "#aé", "+f+f+f"] {
+            assert!(parse_hex(s).is_err(), "{} was accepted", s);
+        }
+    }
+}
@@ -1,23 +1,24 @@
 use std::io::{stdin,stdout,Write};
 use std::path::{PathBuf, Path};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use ndarray::Array3;
+use rayon::{ThreadPool, ThreadPoolBuilder, ThreadPoolBuildError};
+#[cfg(feature = "gui")]
 use fltk::{app::App, frame::Frame, window::Window, image::RgbImage, enums::ColorDepth, prelude::*};
+#[cfg(feature = "gui")]
 use native_dialog::FileDialog;
 
-use crate::imgarray::AsImage;
-use crate::filters::{Filter, Manipulate, CommandParse};
-
-use crate::filters::sepia::Sepia;
-use crate::filters::invert::Invert;
-use crate::filters::grayscale::Grayscale;
-use crate::filters::threshold::Threshold;
-use crate::filters::vignette::Vignette;
-use crate::filters::huerotate::Huerotate;
-use crate::filters::sharpen::Sharpen;
-use crate::filters::lighting::Lighting;
-use crate::filters::blur::Blur;
-use crate::filters::bilateral::Bilateral;
-use crate::filters::compose::Compose;
+use magrustte::imgarray::AsImage;
+use magrustte::color::to_hex;
+use magrustte::linalg::{BorderMode, ResampleKernel};
+use magrustte::progress::Progress;
+use magrustte::filters::{Manipulate, Param, ParamKind, parser, usages};
+use magrustte::error::MagrustteError;
+use magrustte::filters::blur::{self, Blur, BlurMode};
+use magrustte::filters::quantize::{self, Quantize, QuantizeMethod};
+use magrustte::filters::resize::{Resize, ResizeTarget, ResizeMode};
+use magrustte::filters::compose::{Compose, MIN_TILE_SIZE};
 
 pub struct Context {
     pub path: PathBuf,
@@ -26,6 +27,16 @@ pub struct Context {
     pub is_img_open: bool,
     pub filters_composed: Compose,
     pub is_running: bool,
+    // never opens windows or dialogs, e.g. on servers without a display
+    pub headless: bool,
+    // filters run on this pool, the number of threads doesn't change the results
+    pub pool: ThreadPool,
+    // largest dimension of the downscaled copy of the image that is shown instead of it, None shows the full image
+    pub preview_size: Option<usize>,
+    pub preview_img: Array3<u8>,
+    pub preview_res_img: Array3<u8>,
+    // render in progress, which Ctrl-C cancels
+    pub rendering: Arc<Mutex<Option<Arc<Progress>>>>,
 }
 
 impl Context {
@@ -34,8 +45,36 @@ impl Context {
         self.init_img = Array3::<u8>::zeros((1, 1, 3));
         self.res_img = Array3::<u8>::zeros((1, 1, 3));
         self.is_img_open = false;
-        self.filters_composed = Compose::new(vec![]);
+        self.filters_composed.clear();
         self.is_running = true;
+        self.preview_img = Array3::<u8>::zeros((1, 1, 3));
+        self.preview_res_img = Array3::<u8>::zeros((1, 1, 3));
+    }
+
+    // downscales the opened image to the preview size, images already small enough are previewed as they are
+    pub fn update_preview(&mut self) {
+        let (width, height, _) = self.init_img.dim();
+        self.preview_img = match self.preview_size {
+            Some(size) if width.max(height) > size => {
+                let target = ResizeTarget::Pixels(size, size);
+                self.pool.install(|| Resize::with_options(target, ResampleKernel::Bilinear, ResizeMode::Fit).apply(&self.init_img))
+            },
+            _ => self.init_img.clone(),
+        };
+        self.filters_composed.preview_rerender_index = 0;
+    }
+
+    // how much smaller the preview is than the full size image
+    pub fn preview_factor(&self) -> f64 {
+        let (width, height, _) = self.init_img.dim();
+        let (preview_width, preview_height, _) = self.preview_img.dim();
+        preview_width.max(preview_height) as f64 / width.max(height) as f64
+    }
+
+    // 0 uses all logical cores
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), ThreadPoolBuildError> {
+        self.pool = ThreadPoolBuilder::new().num_threads(threads).build()?;
+        Ok(())
     }
 }
 
@@ -48,6 +87,12 @@ impl Default for Context {
             is_img_open: false,
             filters_composed: Compose::new(vec![]),
             is_running: true,
+            headless: false,
+            pool: ThreadPoolBuilder::new().build().unwrap(),
+            preview_size: None,
+            preview_img: Array3::<u8>::zeros((1, 1, 3)),
+            preview_res_img: Array3::<u8>::zeros((1, 1, 3)),
+            rendering: Arc::new(Mutex::new(None)),
         }
     }
 }
@@ -56,14 +101,39 @@ pub fn get_user_input() -> Vec<String> {
     let mut input = String::new();
     print!("> ");
     let _ = stdout().flush();
-    stdin().read_line(&mut input).expect("Did not enter a correct string");
+    // the end of input, e.g. a closed pipe, exits like typing 'exit'
+    match stdin().read_line(&mut input) {
+        Ok(0) => return vec!["exit".to_string()],
+        Ok(_) => (),
+        Err(e) => {
+            println!("Unable to read the command: {}", e);
+            return vec!["exit".to_string()];
+        },
+    }
     input
-        .trim().to_string().split(' ')
+        .trim().split(' ')
         .map(|s| s.to_string())
         .collect::<Vec<String>>()
 }
 
+// parameters of the commands, checked the same way as the parameters of filters
+const FILTER: Param = Param::required("filter", ParamKind::Text("one of the filters listed by 'help'"), "blur");
+const FILENAME: Param = Param::required("filename", ParamKind::Text("a file name like result.png"), "result.png");
+const PREVIEW_SIZE: Param = Param::optional("max size", ParamKind::Text("a whole number of pixels or off"), None, "800");
+const THREADS: Param = Param::optional("number of threads", ParamKind::Int(0, i32::MAX), None, "4");
+const TILE_SIZE: Param = Param::optional("size", ParamKind::Text("a whole number of pixels or off"), None, "512");
+const BENCH_FILTER: Param = Param::required("filter", ParamKind::Choice(&["blur"]), "blur");
+
+// a size in pixels, None for 'off'
+fn parse_size(param: Param, command: &str, value: &str) -> Result<Option<usize>, MagrustteError> {
+    param.parse_with(command, value, |s| match s {
+        "off" => Ok(None),
+        s => s.parse::<usize>().map(Some),
+    })
+}
+
 pub fn driver(ctx: &mut Context, command: Vec<String>) {
+    let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
     match command[0].as_ref() {
         "open" => {
             if ctx.is_img_open {
@@ -71,22 +141,32 @@ pub fn driver(ctx: &mut Context, command: Vec<String>) {
             }
             ctx.path = if command.len() > 1 {
                 PathBuf::from(command[1].as_str())
+            } else if let Some(reason) = no_display(ctx) {
+                println!("Unable to open a file dialog, {}. Type 'open <path>' instead.", reason);
+                return;
             } else {
                 match handle_file_dialog() {
-                    Ok(path) => path,
+                    Ok(Some(path)) => path,
+                    Ok(None) => {
+                        println!("No file was chosen.");
+                        return;
+                    },
                     Err(e) => {
                         println!("{}", e);
                         return;
                     },
                 }
             };
-            match Array3::read(ctx.path.clone().into_os_string().to_str().unwrap()) {
+            match Array3::read(&ctx.path.to_string_lossy()) {
                 Ok(img) => {
                     ctx.is_img_open = true;
                     println!("Image loaded.");
                     ctx.init_img = img;
+                    if ctx.preview_size.is_some() {
+                        ctx.update_preview();
+                    }
                 }
-                Err(_) => println!("Unable to open image: {}", command[1].as_str()),
+                Err(e) => println!("{}", e),
             }
         },
         "open-debug" => {
@@ -94,58 +174,167 @@ pub fn driver(ctx: &mut Context, command: Vec<String>) {
                 println!("Image already loaded, close it first by typing 'close'.");
             }
             ctx.path = PathBuf::from("./przyklady/reze_noise.jpg");
-            match Array3::read(ctx.path.clone().into_os_string().to_str().unwrap()) {
+            match Array3::read(&ctx.path.to_string_lossy()) {
                 Ok(img) => {
                     ctx.is_img_open = true;
                     println!("Image loaded.");
                     ctx.init_img = img;
+                    if ctx.preview_size.is_some() {
+                        ctx.update_preview();
+                    }
                 }
-                Err(_) => println!("Unable to open image: {}", command[1].as_str()),
+                Err(e) => println!("{}", e),
             }
         },
         "add" => {
-            if command.len() < 2 {
-                println!("Wrong number of arguments. Type 'help' to see available commands.");
-            }
-            else if ctx.is_img_open {
-                handle_add(ctx, command[1..].to_vec());
+            if !ctx.is_img_open {
+                println!("{}", MagrustteError::NoImage);
+            } else if let Err(e) = FILTER.parse::<String>("add", arg(1)) {
+                println!("{}", e);
             } else {
-                println!("No image loaded.");
+                handle_add(ctx, command[1..].to_vec());
             }
         },
         "remove" => {
-            if command.len() < 2 {
-                println!("Wrong number of arguments. Type 'help' to see available commands.");
+            if !ctx.is_img_open {
+                println!("{}", MagrustteError::NoImage);
+                return;
             }
-            else if ctx.is_img_open {
-                match command[1].parse::<usize>() {
-                    Ok(index) => {
-                        if index < ctx.filters_composed.len() {
-                            ctx.filters_composed.remove(index);
-                            println!("Filter at index {} removed.", index);
-                        } else {
-                            println!("Index out of bounds.");
-                        }
-                    },
-                    Err(_) => println!("Wrong argument. Type 'help' to see available commands."),
-                }
-            } else {
-                println!("No image loaded.");
+            if ctx.filters_composed.is_empty() {
+                println!("There are no filters to remove.");
+                return;
+            }
+            // the range depends on how many filters there are
+            let index = Param::required("index", ParamKind::Int(0, ctx.filters_composed.len() as i32 - 1), "0");
+            match index.parse::<usize>("remove", arg(1)) {
+                Ok(index) => {
+                    ctx.filters_composed.remove(index);
+                    println!("Filter at index {} removed.", index);
+                },
+                Err(e) => println!("{}", e),
             }
         }
         "list" => {
             if ctx.is_img_open {
                 println!("{}", ctx.filters_composed.details_str());
             } else {
-                println!("No image loaded.");
+                println!("{}", MagrustteError::NoImage);
             }
         },
         "show" => {
-            if ctx.is_img_open {
-                render_image(ctx);
-                show_img(&ctx.res_img);
-            } else {
-                println!("No image loaded.");
+            if !ctx.is_img_open {
+                println!("{}", MagrustteError::NoImage);
+            } else if let Some(reason) = no_display(ctx) {
+                println!("Unable to show the image, {}. Type 'save <filename>' instead.", reason);
+            } else if ctx.preview_size.is_some() {
+                if let Err(e) = render_preview(ctx).and_then(|()| show_img(&ctx.preview_res_img)) {
+                    println!("{}", e);
+                }
+            } else if let Err(e) = render_image(ctx).and_then(|()| show_img(&ctx.res_img)) {
+                println!("{}", e);
+            }
+        },
+        "preview" => {
+            match parse_size(PREVIEW_SIZE, "preview", arg(1)) {
+                _ if arg(1).is_empty() => (),
+                Ok(None) => {
+                    ctx.preview_size = None;
+                    ctx.preview_img = Array3::<u8>::zeros((1, 1, 3));
+                    ctx.preview_res_img = Array3::<u8>::zeros((1, 1, 3));
+                },
+                Ok(Some(size)) => {
+                    ctx.preview_size = Some(size.max(1));
+                    if ctx.is_img_open {
+                        ctx.update_preview();
+                    }
+                },
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                },
+            }
+            match ctx.preview_size {
+                Some(_) if ctx.is_img_open => println!("Showing a {}x{} preview, save renders the full image.",
+                    ctx.preview_img.dim().0, ctx.preview_img.dim().1),
+                Some(size) => println!("Showing previews up to {}x{}, save renders the full image.", size, size),
+                None => println!("Showing the full image."),
+            }
+        },
+        "palette" => {
+            if !ctx.is_img_open {
+                println!("{}", MagrustteError::NoImage);
+                return;
+            }
+            let colors = quantize::COLORS.parse::<i32>("palette", arg(1));
+            let method = quantize::METHOD.parse::<QuantizeMethod>("palette", arg(2));
+            match (colors, method) {
+                (Ok(colors), Ok(method)) => {
+                    if let Err(e) = render_image(ctx) {
+                        println!("{}", e);
+                        return;
+                    }
+                    for (color, share) in Quantize::new(colors, method).dominant_colors(&ctx.res_img) {
+                        println!("{} {:>6.2}%", to_hex(&color), share * 100.0);
+                    }
+                },
+                (Err(e), _) | (_, Err(e)) => println!("{}", e),
+            }
+        },
+        "profile" => {
+            if !ctx.is_img_open {
+                println!("{}", MagrustteError::NoImage);
+                return;
+            }
+            // every filter is rendered again, the result stays the same so the cache remains valid
+            let profiling = ctx.filters_composed.profiling;
+            ctx.filters_composed.profiling = true;
+            ctx.filters_composed.rerender_index = 0;
+            if let Err(e) = render_image(ctx) {
+                println!("{}", e);
+            }
+            ctx.filters_composed.profiling = profiling;
+        },
+        "bench" => {
+            if !ctx.is_img_open {
+                println!("{}", MagrustteError::NoImage);
+                return;
+            }
+            if let Err(e) = BENCH_FILTER.parse_with("bench", arg(1), |s| if s == "blur" { Ok(()) } else { Err(()) }) {
+                println!("{}", e);
+                return;
+            }
+            let radius = blur::RADIUS.parse::<i32>("bench blur", arg(2));
+            let mode = blur::MODE.parse::<BlurMode>("bench blur", arg(3));
+            let border = blur::BORDER.parse::<BorderMode>("bench blur", arg(4));
+            match (radius, mode, border) {
+                (Ok(radius), Ok(mode), Ok(border)) =>
+                    ctx.pool.install(|| bench_blur(&ctx.init_img, Blur::new(radius, mode).with_border(border))),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => println!("{}", e),
+            }
+        },
+        "threads" => {
+            match THREADS.parse::<usize>("threads", arg(1)) {
+                _ if arg(1).is_empty() => println!("Using {} threads.", ctx.pool.current_num_threads()),
+                Ok(threads) => match ctx.set_threads(threads) {
+                    Ok(_) => println!("Using {} threads.", ctx.pool.current_num_threads()),
+                    Err(e) => println!("Unable to start threads: {}", e),
+                },
+                Err(e) => println!("{}", e),
+            }
+        },
+        "tiles" => {
+            // tiling doesn't change the results, so nothing has to be rendered again
+            match parse_size(TILE_SIZE, "tiles", arg(1)) {
+                _ if arg(1).is_empty() => (),
+                Ok(size) => ctx.filters_composed.tile_size = size.map(|size| size.max(MIN_TILE_SIZE)),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                },
+            }
+            match ctx.filters_composed.tile_size {
+                Some(size) => println!("Processing in {}x{} tiles.", size, size),
+                None => println!("Processing whole images."),
             }
         },
         "close" => {
@@ -153,156 +342,206 @@ pub fn driver(ctx: &mut Context, command: Vec<String>) {
                 ctx.clear();
                 println!("Image closed.");
             } else {
-                println!("No image loaded.");
+                println!("{}", MagrustteError::NoImage);
             }
         },
         "save" => {
             if !ctx.is_img_open {
-                println!("No image loaded.");
+                println!("{}", MagrustteError::NoImage);
+                return;
+            }
+            if let Err(e) = FILENAME.parse::<String>("save", arg(1)) {
+                println!("{}", e);
                 return;
             }
-            if command.len() < 2 {
-                println!("Wrong number of arguments. Type 'help' to see available commands.");
+            if let Err(e) = render_image(ctx) {
+                println!("{}", e);
                 return;
             }
-            render_image(ctx);
-            let dest = Path::join(ctx.path.parent().unwrap(), command[1].as_str())
-                .into_os_string().to_str().unwrap().to_string();
-            ctx.res_img.save(&dest);
-            println!("Image saved at {}.", dest);
+            // relative to the opened image, paths without a parent are relative to the working directory
+            let dest = ctx.path.parent().unwrap_or(Path::new("")).join(arg(1))
+                .to_string_lossy().to_string();
+            match ctx.res_img.save(&dest) {
+                Ok(()) => println!("Image saved at {}.", dest),
+                Err(e) => println!("{}", e),
+            }
         },
         "exit" => {
             ctx.is_running = false;
         },
         "help" => {
+            if let Some(name) = command.get(1).filter(|s| !s.is_empty()) {
+                match parser(name) {
+                    Some(parser) => println!("{}", (parser.usage)().help_str()),
+                    None => println!("{}", MagrustteError::UnknownFilter(name.to_string())),
+                }
+                return;
+            }
             println!("Available commands:");
-            println!("open - open image");
+            println!("open <*path> - open image, without a path a file dialog is shown");
             println!("add <filter> <*params> - add filter to image");
             println!("remove <index> - remove filter from image by index");
-            println!("list - list all filters");
-            println!("show - show image");
+            println!("list - list all filters, with their render times once rendered");
+            println!("palette <colors> <*mediancut/kmeans> - print dominant colors of the rendered image");
+            println!("show - show image, unavailable headless");
+            println!("preview <*max size/off> - show a downscaled copy of the image with filter radii scaled to match, save still renders the full image");
+            println!("profile - render every filter again and print its time, memory estimate and output hash");
+            println!("bench blur <radius> <gaussian/box/median> <*border> - time the blur against a full kernel convolution of the opened image");
+            println!("threads <*n> - show or set the number of threads filters run on, 0 uses all cores");
+            println!("tiles <*size/off> - show or set the tile size used to process large images with bounded memory");
             println!("close - close image");
-            println!("save <filename> - save image");
+            println!("save <filename> - save image, png and gif files with up to 256 colors are saved as indexed with their own palette");
             println!("exit - exit program");
-            println!("help - show this message");
-            println!("\nAvailable filters:");
-            println!("sepia");
-            println!("invert");
-            println!("grayscale");
-            println!("threshold <value>");
-            println!("vignette <radius>");
-            println!("huerotate <degrees>");
-            println!("sharpen <gaussian/box/median/bilateral> <radius> <render fine mask>");
-            println!("lighting <brightness> <contrast>");
-            println!("blur <radius> <gaussian/box/median>");
-            println!("bilateral <radius> <spatial sigma> <color sigma>");
+            println!("help <*filter> - show this message, or the parameters of a filter with examples");
+            println!("\nAvailable filters, parameters marked with * are optional:");
+            for usage in usages() {
+                for line in usage.syntax_lines() {
+                    println!("{}", line);
+                }
+            }
         },
         _ => println!("Unknown command. Type 'help' to see available commands."),
     }
 }
 
-fn handle_file_dialog() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let pwd = std::env::current_dir()?;
-    match FileDialog::new()
+fn bench_blur(img: &Array3<u8>, mut blur: Blur) {
+    let start = Instant::now();
+    let reference = blur.apply_reference(img);
+    let reference_time = start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    let fast = blur.apply(img);
+    let fast_time = start.elapsed().as_secs_f64();
+
+    let differences = reference.iter().zip(fast.iter()).map(|(a, b)| a.abs_diff(*b)).collect::<Vec<u8>>();
+    let max_difference = differences.iter().max().copied().unwrap_or(0);
+    let mean_difference = differences.iter().map(|&d| d as f64).sum::<f64>() / differences.len().max(1) as f64;
+    println!("{}", blur.details_str());
+    println!("reference: {:.3}s, fast: {:.3}s ({:.1}x), pixel difference: {} max, {:.3} mean",
+        reference_time, fast_time, reference_time / fast_time.max(f64::EPSILON), max_difference, mean_difference);
+}
+
+// None when no file was chosen
+#[cfg(feature = "gui")]
+fn handle_file_dialog() -> Result<Option<PathBuf>, MagrustteError> {
+    let pwd = std::env::current_dir().map_err(|source| MagrustteError::Io { path: ".".to_string(), source })?;
+    FileDialog::new()
         .set_location(&pwd)
         .add_filter("Image (png, jpg)", &["png", "jpg", "jpeg"])
-        .show_open_single_file() {
-            Ok(op) => match op {
-                Some(p) => Ok(p),
-                None => Err(Box::try_from("No file was chosen".to_string()).unwrap()),
-            },
-            Err(e) => Err(Box::try_from(e.to_string()).unwrap()),
-        }
+        .show_open_single_file()
+        .map_err(|e| MagrustteError::NoDisplay(e.to_string()))
+}
+
+#[cfg(not(feature = "gui"))]
+fn handle_file_dialog() -> Result<Option<PathBuf>, MagrustteError> {
+    Err(MagrustteError::NoDisplay("this build has no gui".to_string()))
 }
 
 fn handle_add(ctx: &mut Context, command: Vec<String>) {
-    // TODO: impl CommandParse for Filter to avoid TERRIBLE code duplication
-    // DRY code not feasible atm since dyn CommandParse structs would have to be passed as params
-    // or maybe try a macro?????
-    match command[0].as_ref() {
-        "sepia" => {
-            ctx.filters_composed.add(Filter::Sepia(Sepia::new()));
-            println!("Sepia filter added.");
-        },
-        "invert" => {
-            ctx.filters_composed.add(Filter::Invert(Invert::new()));
-            println!("Invert filter added.");
-        },
-        "grayscale" => {
-            ctx.filters_composed.add(Filter::Grayscale(Grayscale::new()));
-            println!("Grayscale filter added.");
-        },
-        "threshold" => match Threshold::parse(command[1..].to_vec()) {
-            Ok(filter) => {
-                ctx.filters_composed.add(filter);
-                println!("Threshold filter added.");
-            },
-            Err(_) => println!("Wrong arguments. Type 'help' to see available commands."),
-        },
-        "vignette" => match Vignette::parse(command[1..].to_vec()) {
+    let name = command[0].as_str();
+    match parser(name) {
+        Some(parser) => match (parser.parse)(command[1..].to_vec()) {
             Ok(filter) => {
                 ctx.filters_composed.add(filter);
-                println!("Vignette filter added.");
+                let mut chars = name.chars();
+                let capitalized = chars.next().map_or(String::new(), |c| c.to_uppercase().chain(chars).collect());
+                println!("{} filter added.", capitalized);
             },
-            Err(_) => println!("Wrong arguments. Type 'help' to see available commands."),
+            Err(e) => println!("{} Type 'help {}' to see its parameters.", e, name),
         },
-        "huerotate" => match Huerotate::parse(command[1..].to_vec()) {
-            Ok(filter) => {
-                ctx.filters_composed.add(filter);
-                println!("Huerotate filter added.");
-            },
-            Err(_) => println!("Wrong arguments. Type 'help' to see available commands."),
-        },
-        "sharpen" => match Sharpen::parse(command[1..].to_vec()) {
-            Ok(filter) => {
-                ctx.filters_composed.add(filter);
-                println!("Sharpen filter added.");
-            },
-            Err(_) => println!("Wrong arguments. Type 'help' to see available commands."),
-        },
-        "lighting" => match Lighting::parse(command[1..].to_vec()) {
-            Ok(filter) => {
-                ctx.filters_composed.add(filter);
-                println!("Lighting filter added.");
-            },
-            Err(_) => println!("Wrong arguments. Type 'help' to see available commands."),
-        },
-        "blur" => match Blur::parse(command[1..].to_vec()) {
-            Ok(filter) => {
-                ctx.filters_composed.add(filter);
-                println!("Blur filter added.");
-            },
-            Err(_) => println!("Wrong arguments. Type 'help' to see available commands."),
-        },
-        "bilateral" => match Bilateral::parse(command[1..].to_vec()) {
-            Ok(filter) => {
-                ctx.filters_composed.add(filter);
-                println!("Bilateral filter added.");
-            },
-            Err(_) => println!("Wrong arguments. Type 'help' to see available commands."),
-        },
-        _ => println!("Unknown filter. Type 'help' to see available filters."),
+        None => println!("{}", MagrustteError::UnknownFilter(name.to_string())),
     }
 }
 
-fn render_image(ctx: &mut Context) {
+// progress of the filters printed over a single line, Ctrl-C cancels the render
+fn render_progress(ctx: &Context) -> Arc<Progress> {
+    let progress = Arc::new(Progress::new(|step: &str, fraction: f64, left: Option<Duration>| {
+        let left = left.map_or(String::new(), |left| format!(", about {}s left", left.as_secs()));
+        print!("\r{:<72}", format!("{}: {:.0}%{}", step, fraction * 100.0, left));
+        let _ = stdout().flush();
+    }));
+    *ctx.rendering.lock().unwrap() = Some(progress.clone());
+    progress
+}
+
+// the previous result is kept when the render was cancelled
+fn finish_render(ctx: &Context, rendered: bool) -> Result<(), MagrustteError> {
+    *ctx.rendering.lock().unwrap() = None;
+    println!();
+    if rendered { Ok(()) } else { Err(MagrustteError::Cancelled) }
+}
+
+fn render_image(ctx: &mut Context) -> Result<(), MagrustteError> {
     println!("Rendering image...");
     // no-op equivalent if no new filters were added
     // will apply only the filters that were added since last show
-    match ctx.filters_composed.rerender_index {
-        0 => ctx.res_img = ctx.filters_composed.apply(&ctx.init_img),
-        _ => ctx.res_img = ctx.filters_composed.apply(&ctx.res_img),
-    };
+    let progress = render_progress(ctx);
+    let res = ctx.pool.install(|| {
+        ctx.filters_composed.apply_incremental(&ctx.init_img, &ctx.res_img, &progress)
+    });
+    let rendered = res.is_some();
+    if let Some(res) = res {
+        ctx.res_img = res;
+    }
+    finish_render(ctx, rendered)?;
+    if ctx.filters_composed.profiling {
+        println!("{}", ctx.filters_composed.profile_str());
+    }
+    Ok(())
+}
+
+// same as render_image for the downscaled preview, which has its own cache
+fn render_preview(ctx: &mut Context) -> Result<(), MagrustteError> {
+    println!("Rendering preview...");
+    let factor = ctx.preview_factor();
+    let progress = render_progress(ctx);
+    let res = ctx.pool.install(|| {
+        ctx.filters_composed.apply_preview(&ctx.preview_img, &ctx.preview_res_img, factor, &progress)
+    });
+    let rendered = res.is_some();
+    if let Some(res) = res {
+        ctx.preview_res_img = res;
+    }
+    finish_render(ctx, rendered)
 }
 
-fn show_img(img: &Array3<u8>) {
+// why images can't be shown and files can't be picked from a dialog, None when they can
+fn no_display(ctx: &Context) -> Option<&'static str> {
+    if !cfg!(feature = "gui") {
+        Some("this build has no gui, rebuild it with the 'gui' feature")
+    } else if ctx.headless {
+        Some("the app runs headless")
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "gui")]
+fn show_img(img: &Array3<u8>) -> Result<(), MagrustteError> {
     let app = App::default();
-    let mut wind = Window::new(100, 100, img.dim().0 as i32, img.dim().1 as i32, "Magrustte");
-    let mut frame = Frame::new(0, 0, img.dim().0 as i32, img.dim().1 as i32, "");
-    frame.set_image(Some(RgbImage::new(
-        &img.to_rgb_image(),
-        img.dim().0 as i32, img.dim().1 as i32, ColorDepth::Rgb8).unwrap()));
+    // the pipeline may have resized the image, so the window is fitted to the screen
+    let (img_width, img_height) = (img.dim().0 as i32, img.dim().1 as i32);
+    let (screen_width, screen_height) = fltk::app::screen_size();
+    let scale = (screen_width * 0.9 / img_width as f64)
+        .min(screen_height * 0.9 / img_height as f64)
+        .min(1.0);
+    let (width, height) = (
+        ((img_width as f64 * scale) as i32).max(1),
+        ((img_height as f64 * scale) as i32).max(1),
+    );
+
+    let mut wind = Window::new(100, 100, width, height, "Magrustte");
+    let mut frame = Frame::new(0, 0, width, height, "");
+    let mut image = RgbImage::new(&img.to_rgb_image(), img_width, img_height, ColorDepth::Rgb8)
+        .map_err(|e| MagrustteError::NoDisplay(e.to_string()))?;
+    image.scale(width, height, true, true);
+    frame.set_image(Some(image));
     wind.end();
     wind.show();
-    app.run().unwrap();
-}
\ No newline at end of file
+    app.run().map_err(|e| MagrustteError::NoDisplay(e.to_string()))
+}
+
+#[cfg(not(feature = "gui"))]
+fn show_img(_img: &Array3<u8>) -> Result<(), MagrustteError> {
+    Err(MagrustteError::NoDisplay("this build has no gui".to_string()))
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+
+/// Every failure that is reported to the user, the messages are meant to be printed as they are.
+#[derive(Debug)]
+pub enum MagrustteError {
+    Io { path: String, source: std::io::Error },
+    Decode { path: String, message: String },
+    Encode { path: String, message: String },
+    UnknownFilter(String),
+    MissingParameter { filter: String, name: String, expected: String },
+    BadParameter { filter: String, name: String, value: String, expected: String },
+    UnexpectedArgument { filter: String, value: String },
+    NoImage,
+    NoDisplay(String),
+    Cancelled,
+}
+
+impl fmt::Display for MagrustteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MagrustteError::Io { path, source } => write!(f, "Unable to access {}: {}.", path, source),
+            MagrustteError::Decode { path, message } => write!(f, "Unable to read image {}: {}.", path, message),
+            MagrustteError::Encode { path, message } => write!(f, "Unable to save image {}: {}.", path, message),
+            MagrustteError::UnknownFilter(name) => write!(f, "Unknown filter '{}'. Type 'help' to see available filters.", name),
+            MagrustteError::MissingParameter { filter, name, expected } =>
+                write!(f, "Missing {} for {}, expected {}.", name, filter, expected),
+            MagrustteError::BadParameter { filter, name, value, expected } =>
+                write!(f, "'{}' is not a valid {} for {}, expected {}.", value, name, filter, expected),
+            MagrustteError::UnexpectedArgument { filter, value } => write!(f, "Unexpected argument '{}' for {}.", value, filter),
+            MagrustteError::NoImage => write!(f, "No image loaded."),
+            MagrustteError::NoDisplay(reason) => write!(f, "No display available, {}.", reason),
+            MagrustteError::Cancelled => write!(f, "Rendering cancelled."),
+        }
+    }
+}
+
+impl std::error::Error for MagrustteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MagrustteError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl MagrustteError {
+    pub fn missing_parameter(filter: &str, name: &str, expected: &str) -> Self {
+        MagrustteError::MissingParameter { filter: filter.to_string(), name: name.to_string(), expected: expected.to_string() }
+    }
+
+    pub fn bad_parameter(filter: &str, name: &str, value: &str, expected: &str) -> Self {
+        MagrustteError::BadParameter {
+            filter: filter.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+            expected: expected.to_string(),
+        }
+    }
+
+    pub fn unexpected_argument(filter: &str, value: &str) -> Self {
+        MagrustteError::UnexpectedArgument { filter: filter.to_string(), value: value.to_string() }
+    }
+}
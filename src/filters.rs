@@ -9,9 +9,28 @@ pub mod lighting;
 pub mod blur;
 pub mod compose;
 pub mod bilateral;
+pub mod channelmixer;
+pub mod posterize;
+pub mod dither;
+pub mod quantize;
+pub mod crop;
+pub mod rotate;
+pub mod flip;
+pub mod resize;
+pub mod perspective;
+pub mod affine;
+pub mod lens;
+pub mod autocrop;
+pub mod deskew;
+pub mod edges;
+pub mod convolve;
 
+use std::sync::Arc;
 use ndarray::Array3;
+use crate::error::MagrustteError;
+use crate::progress::Progress;
 
+/// Every filter of the library, so that pipelines can hold any of them.
 pub enum Filter {
     Sepia(sepia::Sepia),
     Invert(invert::Invert),
@@ -23,11 +42,49 @@ pub enum Filter {
     Lighting(lighting::Lighting),
     Blur(blur::Blur),
     Bilateral(bilateral::Bilateral),
+    ChannelMixer(channelmixer::ChannelMixer),
+    Posterize(posterize::Posterize),
+    Dither(dither::Dither),
+    Quantize(quantize::Quantize),
+    Crop(crop::Crop),
+    Rotate(rotate::Rotate),
+    Flip(flip::Flip),
+    Resize(resize::Resize),
+    Perspective(perspective::Perspective),
+    Affine(affine::Affine),
+    Lens(lens::Lens),
+    Autocrop(autocrop::Autocrop),
+    Deskew(deskew::Deskew),
+    Edges(edges::Edges),
+    Convolve(convolve::Convolve),
     Compose(compose::Compose),
 }
+
+/// An image operation, images are `Array3<u8>` arrays indexed by `[x, y, channel]`.
 pub trait Manipulate {
+    /// Filtered copy of the image, which can have a different size than the input.
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8>;
+    /// The filter and its parameters, as listed by the app.
     fn details_str(&self) -> String;
+
+    /// How far away, in pixels, the inputs of an output pixel can be, None when any pixel of the image can matter.
+    /// Filters reporting a radius can be applied tile by tile with the same results.
+    fn neighborhood(&self) -> Option<usize> {
+        None
+    }
+
+    /// The same filter with its pixel distances multiplied by factor, used to preview the pipeline on a downscaled image.
+    /// None when the filter looks the same at any image size.
+    fn scaled(&self, _factor: f64) -> Option<Filter> {
+        None
+    }
+
+    /// Same as apply, None when the render was cancelled before the filter was done.
+    /// The parallel helpers in linalg report to progress how far along the filter is and stop once it's cancelled.
+    fn apply_with_progress(&mut self, img: &Array3<u8>, progress: &Arc<Progress>) -> Option<Array3<u8>> {
+        let res = progress.run(|| self.apply(img));
+        (!progress.is_cancelled()).then_some(res)
+    }
 }
 
 impl Manipulate for Filter {
@@ -43,6 +100,21 @@ impl Manipulate for Filter {
             Filter::Lighting(lighting) => lighting.apply(img),
             Filter::Blur(blur) => blur.apply(img),
             Filter::Bilateral(bilateral) => bilateral.apply(img),
+            Filter::ChannelMixer(channelmixer) => channelmixer.apply(img),
+            Filter::Posterize(posterize) => posterize.apply(img),
+            Filter::Dither(dither) => dither.apply(img),
+            Filter::Quantize(quantize) => quantize.apply(img),
+            Filter::Crop(crop) => crop.apply(img),
+            Filter::Rotate(rotate) => rotate.apply(img),
+            Filter::Flip(flip) => flip.apply(img),
+            Filter::Resize(resize) => resize.apply(img),
+            Filter::Perspective(perspective) => perspective.apply(img),
+            Filter::Affine(affine) => affine.apply(img),
+            Filter::Lens(lens) => lens.apply(img),
+            Filter::Autocrop(autocrop) => autocrop.apply(img),
+            Filter::Deskew(deskew) => deskew.apply(img),
+            Filter::Edges(edges) => edges.apply(img),
+            Filter::Convolve(convolve) => convolve.apply(img),
             Filter::Compose(compose) => compose.apply(img),
         }
     }
@@ -59,11 +131,458 @@ impl Manipulate for Filter {
             Filter::Lighting(lighting) => lighting.details_str(),
             Filter::Blur(blur) => blur.details_str(),
             Filter::Bilateral(bilateral) => bilateral.details_str(),
+            Filter::ChannelMixer(channelmixer) => channelmixer.details_str(),
+            Filter::Posterize(posterize) => posterize.details_str(),
+            Filter::Dither(dither) => dither.details_str(),
+            Filter::Quantize(quantize) => quantize.details_str(),
+            Filter::Crop(crop) => crop.details_str(),
+            Filter::Rotate(rotate) => rotate.details_str(),
+            Filter::Flip(flip) => flip.details_str(),
+            Filter::Resize(resize) => resize.details_str(),
+            Filter::Perspective(perspective) => perspective.details_str(),
+            Filter::Affine(affine) => affine.details_str(),
+            Filter::Lens(lens) => lens.details_str(),
+            Filter::Autocrop(autocrop) => autocrop.details_str(),
+            Filter::Deskew(deskew) => deskew.details_str(),
+            Filter::Edges(edges) => edges.details_str(),
+            Filter::Convolve(convolve) => convolve.details_str(),
             Filter::Compose(compose) => compose.details_str(),
         }
     }
+
+    fn neighborhood(&self) -> Option<usize> {
+        match self {
+            Filter::Sepia(sepia) => sepia.neighborhood(),
+            Filter::Invert(invert) => invert.neighborhood(),
+            Filter::Grayscale(grayscale) => grayscale.neighborhood(),
+            Filter::Threshold(threshold) => threshold.neighborhood(),
+            Filter::Vignette(vignette) => vignette.neighborhood(),
+            Filter::Huerotate(huerotate) => huerotate.neighborhood(),
+            Filter::Sharpen(sharpen) => sharpen.neighborhood(),
+            Filter::Lighting(lighting) => lighting.neighborhood(),
+            Filter::Blur(blur) => blur.neighborhood(),
+            Filter::Bilateral(bilateral) => bilateral.neighborhood(),
+            Filter::ChannelMixer(channelmixer) => channelmixer.neighborhood(),
+            Filter::Posterize(posterize) => posterize.neighborhood(),
+            Filter::Dither(dither) => dither.neighborhood(),
+            Filter::Quantize(quantize) => quantize.neighborhood(),
+            Filter::Crop(crop) => crop.neighborhood(),
+            Filter::Rotate(rotate) => rotate.neighborhood(),
+            Filter::Flip(flip) => flip.neighborhood(),
+            Filter::Resize(resize) => resize.neighborhood(),
+            Filter::Perspective(perspective) => perspective.neighborhood(),
+            Filter::Affine(affine) => affine.neighborhood(),
+            Filter::Lens(lens) => lens.neighborhood(),
+            Filter::Autocrop(autocrop) => autocrop.neighborhood(),
+            Filter::Deskew(deskew) => deskew.neighborhood(),
+            Filter::Edges(edges) => edges.neighborhood(),
+            Filter::Convolve(convolve) => convolve.neighborhood(),
+            Filter::Compose(compose) => compose.neighborhood(),
+        }
+    }
+
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        match self {
+            Filter::Sepia(sepia) => sepia.scaled(factor),
+            Filter::Invert(invert) => invert.scaled(factor),
+            Filter::Grayscale(grayscale) => grayscale.scaled(factor),
+            Filter::Threshold(threshold) => threshold.scaled(factor),
+            Filter::Vignette(vignette) => vignette.scaled(factor),
+            Filter::Huerotate(huerotate) => huerotate.scaled(factor),
+            Filter::Sharpen(sharpen) => sharpen.scaled(factor),
+            Filter::Lighting(lighting) => lighting.scaled(factor),
+            Filter::Blur(blur) => blur.scaled(factor),
+            Filter::Bilateral(bilateral) => bilateral.scaled(factor),
+            Filter::ChannelMixer(channelmixer) => channelmixer.scaled(factor),
+            Filter::Posterize(posterize) => posterize.scaled(factor),
+            Filter::Dither(dither) => dither.scaled(factor),
+            Filter::Quantize(quantize) => quantize.scaled(factor),
+            Filter::Crop(crop) => crop.scaled(factor),
+            Filter::Rotate(rotate) => rotate.scaled(factor),
+            Filter::Flip(flip) => flip.scaled(factor),
+            Filter::Resize(resize) => resize.scaled(factor),
+            Filter::Perspective(perspective) => perspective.scaled(factor),
+            Filter::Affine(affine) => affine.scaled(factor),
+            Filter::Lens(lens) => lens.scaled(factor),
+            Filter::Autocrop(autocrop) => autocrop.scaled(factor),
+            Filter::Deskew(deskew) => deskew.scaled(factor),
+            Filter::Edges(edges) => edges.scaled(factor),
+            Filter::Convolve(convolve) => convolve.scaled(factor),
+            Filter::Compose(compose) => compose.scaled(factor),
+        }
+    }
+
+    // only compose reports its steps, other filters are reported by the helpers they use
+    fn apply_with_progress(&mut self, img: &Array3<u8>, progress: &Arc<Progress>) -> Option<Array3<u8>> {
+        match self {
+            Filter::Compose(compose) => compose.apply_with_progress(img, progress),
+            filter => {
+                let res = progress.run(|| filter.apply(img));
+                (!progress.is_cancelled()).then_some(res)
+            },
+        }
+    }
 }
 
+/// Filters that can be added with a command of the app.
 pub trait CommandParse {
-    fn parse(command: Vec<String>) -> Result<Filter, Box<dyn std::error::Error>>;
-}
\ No newline at end of file
+    /// Builds the filter from the arguments that follow its name, empty arguments stand for left out ones.
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError>;
+    /// Parameters of the filter, shown by `help <filter>`.
+    fn usage() -> Usage;
+}
+
+/// How a filter is parsed from the arguments of a command and described by 'help'.
+#[derive(Clone, Copy)]
+pub struct FilterParser {
+    pub parse: fn(Vec<String>) -> Result<Filter, MagrustteError>,
+    pub usage: fn() -> Usage,
+}
+
+impl FilterParser {
+    /// The parser of a filter type, usable in constants like [`PARSERS`].
+    pub const fn of<F: CommandParse>() -> Self {
+        Self { parse: F::parse, usage: F::usage }
+    }
+
+    /// As typed in commands.
+    pub fn name(&self) -> &'static str {
+        (self.usage)().name
+    }
+}
+
+/// Every filter that can be parsed, in the order 'help' lists them.
+pub const PARSERS: &[FilterParser] = &[
+    FilterParser::of::<sepia::Sepia>(),
+    FilterParser::of::<invert::Invert>(),
+    FilterParser::of::<grayscale::Grayscale>(),
+    FilterParser::of::<threshold::Threshold>(),
+    FilterParser::of::<vignette::Vignette>(),
+    FilterParser::of::<huerotate::Huerotate>(),
+    FilterParser::of::<sharpen::Sharpen>(),
+    FilterParser::of::<lighting::Lighting>(),
+    FilterParser::of::<blur::Blur>(),
+    FilterParser::of::<bilateral::Bilateral>(),
+    FilterParser::of::<channelmixer::ChannelMixer>(),
+    FilterParser::of::<posterize::Posterize>(),
+    FilterParser::of::<dither::Dither>(),
+    FilterParser::of::<quantize::Quantize>(),
+    FilterParser::of::<crop::Crop>(),
+    FilterParser::of::<rotate::Rotate>(),
+    FilterParser::of::<flip::Flip>(),
+    FilterParser::of::<resize::Resize>(),
+    FilterParser::of::<perspective::Perspective>(),
+    FilterParser::of::<affine::Affine>(),
+    FilterParser::of::<lens::Lens>(),
+    FilterParser::of::<autocrop::Autocrop>(),
+    FilterParser::of::<deskew::Deskew>(),
+    FilterParser::of::<edges::Edges>(),
+    FilterParser::of::<convolve::Convolve>(),
+];
+
+/// The parser of the filter with this name, None if there is no such filter.
+pub fn parser(name: &str) -> Option<&'static FilterParser> {
+    PARSERS.iter().find(|parser| parser.name() == name)
+}
+
+/// Usage of every filter, in the order 'help' lists them.
+pub fn usages() -> Vec<Usage> {
+    PARSERS.iter().map(|parser| (parser.usage)()).collect()
+}
+
+/// Parses comma separated values, e.g. '0.3,0.59,0.11'.
+pub fn parse_list<T: std::str::FromStr>(s: &str) -> Result<Vec<T>, T::Err> {
+    s.split(',').map(|x| x.trim().parse::<T>()).collect()
+}
+
+/// What a parameter of a filter accepts, parse errors and `help <filter>` are generated from it.
+#[derive(Clone, Copy)]
+pub enum ParamKind {
+    /// Whole numbers within an inclusive range.
+    Int(i32, i32),
+    /// Numbers within an inclusive range.
+    Number(f64, f64),
+    /// One of a few words.
+    Choice(&'static [&'static str]),
+    /// A hex color, see [`crate::color::parse_hex`].
+    Color,
+    /// A border mode, see [`crate::linalg::BorderMode`].
+    Border,
+    /// Anything else, described in words.
+    Text(&'static str),
+    /// An optional word, followed by its own parameters if it has any.
+    Keyword(&'static [Param]),
+}
+
+/// A parameter of a filter, usually a constant of the filter's module shared by parsing and its usage.
+#[derive(Clone, Copy)]
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ParamKind,
+    pub required: bool,
+    /// Used when an optional parameter is left out.
+    pub default: Option<&'static str>,
+    /// Value used in the generated examples.
+    pub example: &'static str,
+}
+
+impl Param {
+    /// A parameter that has to be given.
+    pub const fn required(name: &'static str, kind: ParamKind, example: &'static str) -> Self {
+        Self { name, kind, required: true, default: None, example }
+    }
+
+    /// A parameter that can be left out, in which case its default is used if it has one.
+    pub const fn optional(name: &'static str, kind: ParamKind, default: Option<&'static str>, example: &'static str) -> Self {
+        Self { name, kind, required: false, default, example }
+    }
+
+    /// An optional word, followed by the given parameters when it's present.
+    pub const fn keyword(name: &'static str, params: &'static [Param]) -> Self {
+        Self { name, kind: ParamKind::Keyword(params), required: false, default: None, example: name }
+    }
+
+    /// What the parameter accepts, as described in errors and by `help <filter>`.
+    pub fn expected(&self) -> String {
+        match self.kind {
+            ParamKind::Int(min, max) => format!("a whole number{}", range_str(min as f64, max as f64, i32::MIN as f64, i32::MAX as f64)),
+            ParamKind::Number(min, max) => format!("a number{}", range_str(min, max, f64::NEG_INFINITY, f64::INFINITY)),
+            ParamKind::Choice(choices) => match choices.split_last() {
+                Some((last, [])) => last.to_string(),
+                Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+                None => String::new(),
+            },
+            ParamKind::Color => "a hex color like #ff8800".to_string(),
+            ParamKind::Border => crate::linalg::BORDER_MODES.to_string(),
+            ParamKind::Text(description) => description.to_string(),
+            ParamKind::Keyword([]) => "a flag".to_string(),
+            ParamKind::Keyword(params) => format!("followed by {}", params.iter().map(|p| format!("<{}>", p.name)).collect::<Vec<_>>().join(" ")),
+        }
+    }
+
+    /// An empty value stands for the default and is missing without one.
+    /// Numbers outside of the range are rejected rather than clamped, the errors name the filter and the parameter.
+    pub fn parse_with<T, E>(&self, filter: &str, value: &str, parse: impl FnOnce(&str) -> Result<T, E>) -> Result<T, MagrustteError> {
+        let value = match (value, self.default) {
+            ("", Some(default)) => default,
+            _ => value,
+        };
+        let expected = self.expected();
+        if value.is_empty() {
+            return Err(MagrustteError::missing_parameter(filter, self.name, &expected));
+        }
+        // 'inf' and 'nan' parse as floats but no filter can work with them, in lists of numbers either
+        let finite = value.split(',').all(|v| v.trim().parse::<f64>().map_or(true, |v| v.is_finite()));
+        let in_range = finite && match self.kind {
+            ParamKind::Int(min, max) => value.parse::<f64>().is_ok_and(|v| v >= min as f64 && v <= max as f64),
+            ParamKind::Number(min, max) => value.parse::<f64>().is_ok_and(|v| v >= min && v <= max),
+            _ => true,
+        };
+        match in_range {
+            true => parse(value).map_err(|_| MagrustteError::bad_parameter(filter, self.name, value, &expected)),
+            false => Err(MagrustteError::bad_parameter(filter, self.name, value, &expected)),
+        }
+    }
+
+    /// Same as parse_with, with the value's FromStr implementation.
+    pub fn parse<T: std::str::FromStr>(&self, filter: &str, value: &str) -> Result<T, MagrustteError> {
+        self.parse_with(filter, value, |s| s.parse::<T>())
+    }
+
+    /// Comma separated values, as many as one of the accepted counts.
+    pub fn parse_list<T: std::str::FromStr>(&self, filter: &str, value: &str, counts: &[usize]) -> Result<Vec<T>, MagrustteError> {
+        self.parse_with(filter, value, |s| match parse_list::<T>(s) {
+            Ok(values) if counts.contains(&values.len()) => Ok(values),
+            _ => Err(()),
+        })
+    }
+
+    // as shown in the syntax of a filter, choices are listed in place of the name
+    fn syntax(&self) -> String {
+        let inner = match self.kind {
+            ParamKind::Choice(choices) => choices.join("/"),
+            ParamKind::Keyword(params) => params.iter().fold(self.name.to_string(), |s, p| format!("{} {}", s, p.syntax())),
+            _ => self.name.to_string(),
+        };
+        match self.required {
+            true => format!("<{}>", inner),
+            false => format!("<*{}>", inner),
+        }
+    }
+}
+
+fn range_str(min: f64, max: f64, lowest: f64, highest: f64) -> String {
+    match (min > lowest, max < highest) {
+        (true, true) => format!(" from {} to {}", min, max),
+        (true, false) => format!(" of at least {}", min),
+        (false, true) => format!(" of at most {}", max),
+        (false, false) => String::new(),
+    }
+}
+
+/// One way of calling a filter, e.g. `threshold adaptive <mean/gaussian> <block size> <offset>`.
+pub struct Form {
+    /// Words that select the form, given before its parameters.
+    pub keywords: &'static str,
+    pub params: &'static [Param],
+    /// Shown after the syntax, empty when the syntax says it all.
+    pub description: &'static str,
+}
+
+/// Every way of calling a filter, the syntax, parameters and examples listed by 'help' are generated from it.
+pub struct Usage {
+    pub name: &'static str,
+    pub forms: &'static [Form],
+}
+
+impl Usage {
+    /// One line per form, as listed by 'help'.
+    pub fn syntax_lines(&self) -> Vec<String> {
+        self.forms.iter().map(|form| {
+            let words = [self.name, form.keywords].into_iter()
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .chain(form.params.iter().map(|p| p.syntax()))
+                .collect::<Vec<String>>()
+                .join(" ");
+            match form.description {
+                "" => words,
+                description => format!("{} - {}", words, description),
+            }
+        }).collect()
+    }
+
+    /// Syntax, every parameter and examples generated from them, as shown by `help <filter>`.
+    pub fn help_str(&self) -> String {
+        let mut lines = self.syntax_lines();
+
+        let mut params: Vec<&Param> = vec![];
+        for param in self.forms.iter().flat_map(|form| form.params) {
+            let nested = match param.kind {
+                ParamKind::Keyword(nested) => nested,
+                _ => &[],
+            };
+            for param in std::iter::once(param).chain(nested) {
+                if !params.iter().any(|p| p.name == param.name) {
+                    params.push(param);
+                }
+            }
+        }
+        if !params.is_empty() {
+            lines.push("\nParameters:".to_string());
+        }
+        for param in params {
+            let description = match (param.kind, param.required, param.default) {
+                (ParamKind::Keyword([]), _, _) => "optional flag".to_string(),
+                (ParamKind::Keyword(_), _, _) => format!("optional, {}", param.expected()),
+                (_, true, _) => param.expected(),
+                (_, false, Some(default)) => format!("{}, {} by default", param.expected(), default),
+                (_, false, None) => format!("{}, optional", param.expected()),
+            };
+            lines.push(format!("  {:<18} {}", param.name, description));
+        }
+
+        lines.push("\nExamples:".to_string());
+        lines.extend(self.examples().iter().map(|example| format!("  add {}", example)));
+        lines.join("\n")
+    }
+
+    /// Per form, one command with only the required parameters and one with all of them.
+    pub fn examples(&self) -> Vec<String> {
+        let mut examples = vec![];
+        for form in self.forms {
+            let example = |all: bool| [self.name, form.keywords].into_iter()
+                .chain(form.params.iter().filter(|p| all || p.required).flat_map(|p| match p.kind {
+                    ParamKind::Keyword(nested) => std::iter::once(p.example).chain(nested.iter().map(|p| p.example)).collect(),
+                    _ => vec![p.example],
+                }))
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<&str>>()
+                .join(" ");
+            examples.push(example(false));
+            if form.params.iter().any(|p| !p.required) {
+                examples.push(example(true));
+            }
+        }
+        examples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // as typed after 'add'
+    fn parse(command: &str) -> Result<Filter, MagrustteError> {
+        let mut words = command.split(' ').map(|s| s.to_string());
+        let name = words.next().unwrap_or_default();
+        (parser(&name).unwrap().parse)(words.collect())
+    }
+
+    fn missing(command: &str) -> Option<String> {
+        match parse(command) {
+            Err(MagrustteError::MissingParameter { name, .. }) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn bad(command: &str) -> Option<(String, String)> {
+        match parse(command) {
+            Err(MagrustteError::BadParameter { name, value, .. }) => Some((name, value)),
+            _ => None,
+        }
+    }
+
+    fn unexpected(command: &str) -> Option<String> {
+        match parse(command) {
+            Err(MagrustteError::UnexpectedArgument { value, .. }) => Some(value),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn malformed_commands_name_the_argument() {
+        assert!(parser("nope").is_none());
+        assert_eq!(missing("blur"), Some("radius".to_string()));
+        assert_eq!(missing("blur 3"), Some("mode".to_string()));
+        assert_eq!(missing("threshold adaptive mean 15"), Some("offset".to_string()));
+        assert_eq!(bad("blur abc gaussian"), Some(("radius".to_string(), "abc".to_string())));
+        assert_eq!(bad("blur 60 gaussian"), Some(("radius".to_string(), "60".to_string())));
+        assert_eq!(bad("blur 3 fuzzy"), Some(("mode".to_string(), "fuzzy".to_string())));
+        assert_eq!(bad("lens nan"), Some(("k1".to_string(), "nan".to_string())));
+        assert_eq!(bad("vignette 50 inf"), Some(("opacity".to_string(), "inf".to_string())));
+        assert_eq!(unexpected("invert x"), Some("x".to_string()));
+        assert_eq!(unexpected("lens -0.1 0.02 ca 1.002 0.998 extra"), Some("extra".to_string()));
+        assert_eq!(unexpected("threshold 128 perchannel sideways"), Some("sideways".to_string()));
+    }
+
+    #[test]
+    fn help_lists_every_param() {
+        for usage in usages() {
+            let help = usage.help_str();
+            for param in usage.forms.iter().flat_map(|form| form.params) {
+                let nested = match param.kind {
+                    ParamKind::Keyword(nested) => nested,
+                    _ => &[],
+                };
+                for param in std::iter::once(param).chain(nested) {
+                    let listed = format!("  {} ", param.name);
+                    assert!(help.lines().any(|line| line.starts_with(&listed)), "{} of {}", param.name, usage.name);
+                }
+            }
+        }
+    }
+
+    // the examples are generated from the same description as the errors, so they have to parse
+    #[test]
+    fn help_examples_parse() {
+        for usage in usages() {
+            for example in usage.examples() {
+                match parse(&example) {
+                    Ok(_) | Err(MagrustteError::Io { .. }) => (),
+                    Err(e) => panic!("{}: {}", example, e),
+                }
+            }
+        }
+    }
+}
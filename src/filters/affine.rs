@@ -0,0 +1,71 @@
+use ndarray::Array3;
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::filters::perspective::{parse_sampling, INTERPOLATION, SAMPLING_BORDER};
+use crate::linalg::{BorderMode, Interpolation, sample, par_image_from_fn};
+
+pub struct Affine {
+    // [a, b, c, d, e, f] maps a source pixel (x, y) onto (a * x + b * y + c, d * x + e * y + f)
+    matrix: [f64; 6],
+    interpolation: Interpolation,
+    border: BorderMode,
+}
+
+impl Affine {
+    pub fn new(matrix: [f64; 6], interpolation: Interpolation, border: BorderMode) -> Self {
+        Self { matrix, interpolation, border }
+    }
+}
+
+impl Manipulate for Affine {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let (width, height, _) = img.dim();
+        let [a, b, c, d, e, f] = self.matrix;
+        let det = a * e - b * d;
+        if det.abs() < 1e-12 {
+            return img.clone(); // not invertible, everything would collapse onto a line
+        }
+
+        par_image_from_fn(width, height, |x, y| {
+            // inverse mapping, each output pixel is sampled from the source image
+            let (dx, dy) = (x as f64 - c, y as f64 - f);
+            let src_x = (e * dx - b * dy) / det;
+            let src_y = (-d * dx + a * dy) / det;
+            sample(img, src_x, src_y, self.interpolation, &self.border).map(|v| v.clamp(0.0, 255.0).round() as u8)
+        })
+    }
+
+    fn details_str(&self) -> String {
+        let matrix = self.matrix.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",");
+        format!("Affine -> matrix: {}, interpolation: {:?}, border: {:?}",
+            matrix, self.interpolation, self.border)
+    }
+
+    // only the translation is measured in pixels
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        let [a, b, c, d, e, f] = self.matrix;
+        Some(Filter::Affine(Affine::new([a, b, c * factor, d, e, f * factor], self.interpolation, self.border)))
+    }
+}
+
+const MATRIX: Param = Param::required("matrix", ParamKind::Text("6 comma separated numbers a,b,c,d,e,f"), "1,0.2,0,0,1,0");
+
+impl CommandParse for Affine {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let values = MATRIX.parse_list::<f64>("affine", command.first().map_or("", |s| s.as_str()), &[6])?;
+        let matrix = [values[0], values[1], values[2], values[3], values[4], values[5]];
+        let (interpolation, border) = parse_sampling(command.get(1..).unwrap_or(&[]), "affine")?;
+        Ok(Filter::Affine(Affine::new(matrix, interpolation, border)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "affine",
+            forms: &[Form {
+                keywords: "",
+                params: &[MATRIX, INTERPOLATION, SAMPLING_BORDER],
+                description: "maps (x, y) to (ax + by + c, dx + ey + f)",
+            }],
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use ndarray::{Array3, ArrayView2, s};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::color::Rgb;
+use crate::progress::Reporter;
+
+pub struct Autocrop {
+    tolerance: u8,
+}
+
+// share of pixels in a row or column that have to match the border color, so that dust and scan noise is ignored
+const BORDER_COVERAGE: f64 = 0.99;
+
+/// Per channel median of the four corner pixels, robust to a single corner that isn't part of the border.
+/// Empty images have no corners, their border is black.
+pub fn border_color(img: &Array3<u8>) -> Rgb {
+    let (width, height, _) = img.dim();
+    if width == 0 || height == 0 {
+        return [0; 3];
+    }
+    let corners = [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)];
+    let mut res = [0; 3];
+    for (c, val) in res.iter_mut().enumerate() {
+        let mut values = corners.iter().map(|&(x, y)| img[[x, y, c]]).collect::<Vec<u8>>();
+        values.sort();
+        *val = ((values[1] as u16 + values[2] as u16) / 2) as u8;
+    }
+    res
+}
+
+impl Autocrop {
+    pub fn new(tolerance: i32) -> Self {
+        Self { tolerance: tolerance.clamp(0, 255) as u8 }
+    }
+
+    fn is_border_line(&self, line: ArrayView2<u8>, color: &Rgb) -> bool {
+        let matching = line.outer_iter()
+            .filter(|pixel| (0..3).all(|c| pixel[c].abs_diff(color[c]) <= self.tolerance))
+            .count();
+        matching as f64 >= line.dim().0 as f64 * BORDER_COVERAGE
+    }
+}
+
+impl Manipulate for Autocrop {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let (width, height, _) = img.dim();
+        let color = border_color(img);
+        let column = |x: usize| img.slice(s![x, .., ..]);
+        let row = |y: usize| img.slice(s![.., y, ..]);
+        // the scans stop at the first line of content from each side, so every line is checked at most once,
+        // and once cancelled every line counts as content so that they all stop right away
+        let reporter = Reporter::current();
+        reporter.start_pass(width + height);
+        let is_border_line = |line: ArrayView2<u8>| {
+            reporter.line_done();
+            !reporter.is_cancelled() && self.is_border_line(line, &color)
+        };
+
+        let left = (0..width).find(|&x| !is_border_line(column(x)));
+        let left = match left {
+            Some(left) => left,
+            None => return img.clone(), // the whole image is uniform, nothing to keep
+        };
+        let right = (left..width).rev().find(|&x| !is_border_line(column(x))).unwrap_or(left);
+        let top = (0..height).find(|&y| !is_border_line(row(y))).unwrap_or(0);
+        let bottom = (top..height).rev().find(|&y| !is_border_line(row(y))).unwrap_or(top);
+
+        img.slice(s![left..=right, top..=bottom, ..]).to_owned()
+    }
+
+    fn details_str(&self) -> String {
+        format!("Autocrop -> tolerance: {}", self.tolerance)
+    }
+}
+
+const TOLERANCE: Param = Param::optional("tolerance", ParamKind::Int(0, 255), Some("10"), "30");
+
+impl CommandParse for Autocrop {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let tolerance = TOLERANCE.parse::<i32>("autocrop", command.first().map_or("", |s| s.as_str()))?;
+        Ok(Filter::Autocrop(Autocrop::new(tolerance)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "autocrop", forms: &[Form { keywords: "", params: &[TOLERANCE], description: "trims uniform borders" }] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trims_uniform_border() {
+        let mut img = Array3::from_elem((30, 20, 3), 250);
+        img.slice_mut(s![8..19, 5..12, ..]).fill(20);
+        assert_eq!(Autocrop::new(10).apply(&img), img.slice(s![8..19, 5..12, ..]));
+        // a single corner of another color doesn't change the border color
+        img[[0, 0, 0]] = 0;
+        assert_eq!(border_color(&img), [250; 3]);
+    }
+
+    #[test]
+    fn empty_image_stays_empty() {
+        let img = Array3::<u8>::zeros((0, 5, 3));
+        assert_eq!(border_color(&img), [0; 3]);
+        assert_eq!(Autocrop::new(10).apply(&img).dim(), (0, 5, 3));
+    }
+}
@@ -1,87 +1,249 @@
 use ndarray::{Array2, Array3, stack, Axis};
-use crate::filters::{CommandParse, Filter, Manipulate};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, grayscale::Grayscale, Param, ParamKind, Form, Usage};
 use crate::imgarray::AsImage;
-use crate::linalg::{gaussian_kernel, outer_product, gaussian, array_slice};
+use crate::linalg::{gaussian_kernel, outer_product, gaussian, array_slice, pad_array, par_from_fn, BorderMode};
 
 pub struct Bilateral {
     radius: i32,
     diameter: i32,
     spatial_sigma: f64,
     color_sigma: f64,
+    border: BorderMode,
+    fast: bool,
+    joint: bool,
 }
 
+// how many cells the grid blur reaches along the intensity axis
+const GRID_INTENSITY_CELLS: usize = 2;
+
 impl Bilateral {
     pub fn new(radius: i32, spatial_sigma: f64, intensity_sigma: f64) -> Self {
         Self {
-            radius: radius.max(0).min(50),
-            diameter: radius.max(0).min(50) * 2 + 1,
-            spatial_sigma: spatial_sigma.max(0.1).min(50.0),
-            color_sigma: intensity_sigma.max(0.1).min(50.0),
+            radius: radius.clamp(0, 50),
+            diameter: radius.clamp(0, 50) * 2 + 1,
+            spatial_sigma: spatial_sigma.clamp(0.1, 50.0),
+            color_sigma: intensity_sigma.clamp(0.1, 50.0),
+            border: BorderMode::Clamp,
+            fast: false,
+            joint: false,
         }
     }
 
+    pub fn with_border(mut self, border: BorderMode) -> Self {
+        self.border = border;
+        self
+    }
+
+    /// Fast approximates the filter with a bilateral grid, joint takes color weights from luminance for all channels.
+    pub fn with_modes(mut self, fast: bool, joint: bool) -> Self {
+        self.fast = fast;
+        self.joint = joint;
+        self
+    }
+
     // https://python.algorithmexamples.com/web/digital_image_processing/filters/bilateral_filter.html
-    fn bilateral_channel(&self, channel: &Array2<f64>) -> Array2<f64> {
+    // color weights come from the guide, which is the channel itself unless the filter is joint
+    fn bilateral_channel(&self, channel: &Array2<f64>, fill: f64, guide: Option<(&Array2<f64>, f64)>) -> Array2<f64> {
         let (width, height) = channel.dim();
-        let mut res = Array2::<f64>::zeros((width, height));
         let spatial_kernel = outer_product(
             &gaussian_kernel(self.diameter, self.spatial_sigma),
             &gaussian_kernel(self.diameter, self.spatial_sigma));
 
-        for x in 0..width {
-            for y in 0..height {
-                let img_s = array_slice(channel, x as i32, y as i32, self.radius);
-                let img_i = &img_s - channel[[x, y]];
-                let img_ig = img_i.mapv(|x| gaussian(x, 0.0, self.color_sigma));
-                let weights = &spatial_kernel * &img_ig;
-                let vals = &img_s * &weights;
-                let val = vals.sum() / weights.sum();
-                res[[x, y]] = val;
+        par_from_fn(width, height, |x, y| {
+            let img_s = array_slice(channel, x as i32, y as i32, self.radius, &self.border, fill);
+            let img_i = match guide {
+                Some((guide, guide_fill)) =>
+                    array_slice(guide, x as i32, y as i32, self.radius, &self.border, guide_fill) - guide[[x, y]],
+                None => &img_s - channel[[x, y]],
+            };
+            let img_ig = img_i.mapv(|x| gaussian(x, 0.0, self.color_sigma));
+            let weights = &spatial_kernel * &img_ig;
+            let vals = &img_s * &weights;
+            vals.sum() / weights.sum()
+        })
+    }
+
+    // grid cells are blurred with a gaussian with a sigma of one cell, cut off after as many cells as the radius
+    // spans in space, the same way the full filter only looks as far as the radius
+    fn grid_kernel(cells: usize) -> Vec<f64> {
+        let cells = cells as i32;
+        let kernel = (-cells..=cells).map(|i| gaussian(i as f64, 0.0, 1.0)).collect::<Vec<f64>>();
+        let sum = kernel.iter().sum::<f64>();
+        kernel.iter().map(|w| w / sum).collect()
+    }
+
+    // https://people.csail.mit.edu/sparis/publi/2009/ijcv/Paris_09_Fast_Approximation.pdf
+    // pixels are accumulated in a coarse (x, y, intensity) grid with cells one sigma wide,
+    // which is blurred and then sampled back at every pixel's position and intensity
+    fn bilateral_grid_channel(&self, channel: &Array2<f64>, fill: f64, guide: Option<(&Array2<f64>, f64)>) -> Array2<f64> {
+        let (width, height) = channel.dim();
+        let cell = self.spatial_sigma.max(1.0);
+        let cells = (self.radius as f64 / cell).ceil().max(1.0) as usize;
+        // the padding brings the border mode into the cells the grid blur reaches
+        let pad = (cells as f64 * cell).ceil() as usize;
+        let padded = pad_array(channel, pad, &self.border, fill);
+        let padded_guide = match guide {
+            Some((guide, guide_fill)) => pad_array(guide, pad, &self.border, guide_fill),
+            None => padded.clone(),
+        };
+
+        let (padded_width, padded_height) = padded.dim();
+        let grid_dim = (
+            ((padded_width - 1) as f64 / cell) as usize + 2,
+            ((padded_height - 1) as f64 / cell) as usize + 2,
+            (1.0 / self.color_sigma) as usize + 2,
+        );
+        let mut values = Array3::<f64>::zeros(grid_dim);
+        let mut weights = Array3::<f64>::zeros(grid_dim);
+
+        for ((x, y), &val) in padded.indexed_iter() {
+            let i = (x as f64 / cell).round() as usize;
+            let j = (y as f64 / cell).round() as usize;
+            let k = (padded_guide[[x, y]].clamp(0.0, 1.0) / self.color_sigma).round() as usize;
+            values[[i, j, k]] += val;
+            weights[[i, j, k]] += 1.0;
+        }
+
+        let (spatial_kernel, intensity_kernel) = (Self::grid_kernel(cells), Self::grid_kernel(GRID_INTENSITY_CELLS));
+        for (axis, kernel) in [(0, &spatial_kernel), (1, &spatial_kernel), (2, &intensity_kernel)] {
+            Self::blur_grid(&mut values, Axis(axis), kernel);
+            Self::blur_grid(&mut weights, Axis(axis), kernel);
+        }
+
+        par_from_fn(width, height, |x, y| {
+            let (x_, y_) = (x + pad, y + pad);
+            let position = [
+                x_ as f64 / cell,
+                y_ as f64 / cell,
+                padded_guide[[x_, y_]].clamp(0.0, 1.0) / self.color_sigma,
+            ];
+            let weight = Self::trilinear(&weights, position);
+            if weight > 0.0 { Self::trilinear(&values, position) / weight } else { padded[[x_, y_]] }
+        })
+    }
+
+    fn blur_grid(grid: &mut Array3<f64>, axis: Axis, kernel: &[f64]) {
+        let radius = kernel.len() / 2;
+        for mut lane in grid.lanes_mut(axis) {
+            let original = lane.to_vec();
+            for (i, val) in lane.iter_mut().enumerate() {
+                *val = kernel.iter()
+                    .enumerate()
+                    .filter_map(|(k, w)| (i + k).checked_sub(radius).and_then(|i_| original.get(i_)).map(|v| v * w))
+                    .sum();
+            }
+        }
+    }
+
+    fn trilinear(grid: &Array3<f64>, position: [f64; 3]) -> f64 {
+        let (dim_x, dim_y, dim_z) = grid.dim();
+        let index = |p: f64, dim: usize| (p.floor() as usize).min(dim - 2);
+        let (i, j, k) = (index(position[0], dim_x), index(position[1], dim_y), index(position[2], dim_z));
+        let (tx, ty, tz) = (position[0] - i as f64, position[1] - j as f64, position[2] - k as f64);
+
+        let mut res = 0.0;
+        for (di, wx) in [(0, 1.0 - tx), (1, tx)] {
+            for (dj, wy) in [(0, 1.0 - ty), (1, ty)] {
+                for (dk, wz) in [(0, 1.0 - tz), (1, tz)] {
+                    res += grid[[i + di, j + dj, k + dk]] * wx * wy * wz;
+                }
             }
         }
         res
     }
+
+    fn filter_channel(&self, channel: &Array2<f64>, fill: f64, guide: Option<(&Array2<f64>, f64)>) -> Array2<f64> {
+        if self.fast {
+            self.bilateral_grid_channel(channel, fill, guide)
+        } else {
+            self.bilateral_channel(channel, fill, guide)
+        }
+    }
 }
 
 impl Manipulate for Bilateral {
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
         let (rc , gc, bc) = img.rgb_as_float();
 
+        let luma = Grayscale::new().luma(img) / 255.0;
+        let luma_fill = match self.border {
+            BorderMode::Constant(color) =>
+                Grayscale::new().luma(&Array3::from_shape_fn((1, 1, 3), |(_, _, c)| color[c]))[[0, 0]] / 255.0,
+            _ => 0.0,
+        };
+        let guide = if self.joint { Some((&luma, luma_fill)) } else { None };
+
         stack(Axis(2), &[
-            self.bilateral_channel(&(rc / 255.0))
-                .mapv(|x| (x * 255.0).min(255.0).max(0.0) as u8).view(),
-            self.bilateral_channel(&(gc / 255.0))
-                .mapv(|x| (x * 255.0).min(255.0).max(0.0) as u8).view(),
-              self.bilateral_channel(&(bc / 255.0))
-                  .mapv(|x| (x * 255.0).min(255.0).max(0.0) as u8).view(),
+            self.filter_channel(&(rc / 255.0), self.border.fill(0) / 255.0, guide)
+                .mapv(|x| (x * 255.0).clamp(0.0, 255.0) as u8).view(),
+            self.filter_channel(&(gc / 255.0), self.border.fill(1) / 255.0, guide)
+                .mapv(|x| (x * 255.0).clamp(0.0, 255.0) as u8).view(),
+            self.filter_channel(&(bc / 255.0), self.border.fill(2) / 255.0, guide)
+                .mapv(|x| (x * 255.0).clamp(0.0, 255.0) as u8).view(),
         ]).unwrap()
     }
 
     fn details_str(&self) -> String {
-        format!("Bilateral filter -> radius: {}, spatial sigma: {}, color sigma: {}",
-            self.radius, self.spatial_sigma, self.color_sigma)
+        let fast = if self.fast { ", fast" } else { "" };
+        let joint = if self.joint { ", joint" } else { "" };
+        format!("Bilateral filter -> radius: {}, spatial sigma: {}, color sigma: {}, border: {:?}{}{}",
+            self.radius, self.spatial_sigma, self.color_sigma, self.border, fast, joint)
+    }
+
+    // the grid cells are aligned to the image origin, and wrapped borders read the opposite edge of the image
+    fn neighborhood(&self) -> Option<usize> {
+        match self.border {
+            _ if self.fast => None,
+            BorderMode::Wrap => None,
+            _ => Some(self.radius as usize),
+        }
+    }
+
+    // color sigma is a difference of intensities, it doesn't depend on the image size
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        let radius = (self.radius as f64 * factor).round() as i32;
+        Some(Filter::Bilateral(Bilateral::new(radius, self.spatial_sigma * factor, self.color_sigma)
+            .with_border(self.border)
+            .with_modes(self.fast, self.joint)))
     }
 }
 
+const RADIUS: Param = Param::required("radius", ParamKind::Int(0, 50), "5");
+const SPATIAL_SIGMA: Param = Param::required("spatial sigma", ParamKind::Number(0.1, 50.0), "3");
+const COLOR_SIGMA: Param = Param::required("color sigma", ParamKind::Number(0.1, 50.0), "0.1");
+const BORDER: Param = Param::optional("border", ParamKind::Border, Some("clamp"), "reflect");
+const FAST: Param = Param::keyword("fast", &[]);
+const JOINT: Param = Param::keyword("joint", &[]);
+
 impl CommandParse for Bilateral {
-    fn parse(command: Vec<String>) -> Result<Filter, Box<dyn std::error::Error>> {
-        let maybe_radius = match command.get(0) {
-            Some(s) => s,
-            None => "nan",
-        };
-        let maybe_spatial_sigma = match command.get(1) {
-            Some(s) => s,
-            None => "nan",
-        };
-        let maybe_color_sigma = match command.get(2) {
-            Some(s) => s,
-            None => "nan",
-        };
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let radius = RADIUS.parse::<i32>("bilateral", arg(0))?;
+        let spatial_sigma = SPATIAL_SIGMA.parse::<f64>("bilateral", arg(1))?;
+        let color_sigma = COLOR_SIGMA.parse::<f64>("bilateral", arg(2))?;
 
-        let radius = maybe_radius.parse::<i32>()?;
-        let spatial_sigma = maybe_spatial_sigma.parse::<f64>()?;
-        let color_sigma = maybe_color_sigma.parse::<f64>()?;
+        // the remaining arguments are optional and can come in any order
+        let (mut border, mut fast, mut joint) = (BorderMode::Clamp, false, false);
+        for option in command.iter().skip(3).filter(|s| !s.is_empty()) {
+            match option.as_str() {
+                "fast" => fast = true,
+                "joint" => joint = true,
+                _ => border = BORDER.parse::<BorderMode>("bilateral", option)?,
+            }
+        }
 
-        Ok(Filter::Bilateral(Bilateral::new(radius, spatial_sigma, color_sigma)))
+        Ok(Filter::Bilateral(Bilateral::new(radius, spatial_sigma, color_sigma).with_border(border).with_modes(fast, joint)))
     }
-}
\ No newline at end of file
+
+    fn usage() -> Usage {
+        Usage {
+            name: "bilateral",
+            forms: &[Form {
+                keywords: "",
+                params: &[RADIUS, SPATIAL_SIGMA, COLOR_SIGMA, BORDER, FAST, JOINT],
+                description: "edge preserving blur, fast approximates with a bilateral grid, joint weighs colors by luminance",
+            }],
+        }
+    }
+}
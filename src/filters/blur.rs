@@ -1,16 +1,23 @@
-use ndarray::{Array2, Array3, stack, Axis};
-use crate::filters::{CommandParse, Filter, Manipulate};
+use ndarray::{Array2, Array3, stack, Axis, s};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
 use crate::imgarray::AsImage;
-use crate::linalg::{gaussian_kernel, outer_product, median};
+use crate::linalg::{gaussian_kernel, outer_product, median, array_slice, BorderMode};
+use crate::linalg::{convolve_bordered, convolve_separable_bordered, box_mean_bordered, gaussian_boxes, median_filter, pad_array};
 
 pub struct Blur {
     radius: i32,
     diameter: i32,
     sigma: f64,
     mode: BlurMode,
+    border: BorderMode,
 }
 
-#[derive(Debug)]
+// above this radius gaussian blur is approximated with repeated box blurs, which cost the same for any radius
+const ITERATED_BOX_RADIUS: i32 = 20;
+const GAUSSIAN_BOXES: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
 pub enum BlurMode {
     Gaussian,
     Box,
@@ -33,52 +40,79 @@ impl std::str::FromStr for BlurMode {
 impl Blur {
     pub fn new(radius: i32, mode: BlurMode) -> Self {
         Self {
-            radius: radius.max(0).min(50),
-            diameter: radius.max(0).min(50) * 2 + 1,
-            sigma: ((radius.max(0).min(50) as f64) / 2.0).max(1.0),
+            radius: radius.clamp(0, 50),
+            diameter: radius.clamp(0, 50) * 2 + 1,
+            sigma: ((radius.clamp(0, 50) as f64) / 2.0).max(1.0),
             mode,
+            border: BorderMode::Clamp,
         }
     }
 
-    fn base_blur_channel(channel: &Array2<f64>, kernel: &Array2<f64>, radius: i32) -> Array2<f64> {
-        let (width, height) = channel.dim();
-        let mut res = Array2::<f64>::zeros((width, height));
-
-        for x in 0..width as i32{
-            for y in 0..height as i32 {
-                let mut new_val = 0.0;
+    pub fn with_border(mut self, border: BorderMode) -> Self {
+        self.border = border;
+        self
+    }
 
-                for i in -radius..radius {
-                    for j in -radius..radius {
-                        // capping the values to the image boundaries so as to not make the edges dimmer
-                        let x_ = (x + i).max(0).min(width as i32 - 1);
-                        let y_ = (y + j).max(0).min(height as i32 - 1);
+    pub fn kernel(&self) -> Array2<f64> {
+        match self.mode {
+            BlurMode::Gaussian => outer_product(
+                &gaussian_kernel(self.diameter, self.sigma),
+                &gaussian_kernel(self.diameter, self.sigma),
+            ),
+            BlurMode::Box => Array2::<f64>::ones((self.diameter as usize, self.diameter as usize)) / (self.diameter * self.diameter) as f64,
+            BlurMode::Median => Array2::<f64>::ones((self.diameter as usize, self.diameter as usize)),
+        }
+    }
 
-                        new_val += channel[[x_ as usize, y_ as usize]] * kernel[[(radius + i) as usize, (radius + j) as usize]];
-                    }
-                }
-                res[[x as usize, y as usize]] = new_val
-            }
+    fn blur_channel(&self, channel: &Array2<f64>, fill: f64) -> Array2<f64> {
+        match self.mode {
+            // padded once by the reach of all the boxes, so that the border mode extends the image itself
+            // rather than the result of every pass
+            BlurMode::Gaussian if self.radius > ITERATED_BOX_RADIUS => {
+                let (width, height) = channel.dim();
+                let boxes = gaussian_boxes(self.sigma, GAUSSIAN_BOXES);
+                let pad = boxes.iter().map(|width| *width as usize / 2).sum::<usize>();
+                boxes.iter()
+                    .fold(pad_array(channel, pad, &self.border, fill), |acc, width| box_mean_bordered(&acc, width / 2, &BorderMode::Clamp, fill))
+                    .slice(s![pad..pad + width, pad..pad + height])
+                    .to_owned()
+            },
+            BlurMode::Gaussian => {
+                let kernel = gaussian_kernel(self.diameter, self.sigma);
+                convolve_separable_bordered(channel, &kernel, &kernel, &self.border, fill)
+            },
+            BlurMode::Box => box_mean_bordered(channel, self.radius, &self.border, fill),
+            BlurMode::Median => median_filter(channel, self.radius, &self.border, fill),
         }
-        res
     }
 
-    fn median_blur_channel(channel: &Array2<f64>, _kernel: &Array2<f64>, radius: i32) -> Array2<f64> {
+    fn blur_image(&self, img: &Array3<u8>, blur_fn: impl Fn(&Array2<f64>, f64) -> Array2<f64>) -> Array3<u8> {
+        let (rc , gc, bc) = img.rgb_as_float();
+
+        stack(Axis(2), &[
+            blur_fn(&rc, self.border.fill(0)).mapv(|x| x.clamp(0.0, 255.0) as u8).view(),
+            blur_fn(&gc, self.border.fill(1)).mapv(|x| x.clamp(0.0, 255.0) as u8).view(),
+            blur_fn(&bc, self.border.fill(2)).mapv(|x| x.clamp(0.0, 255.0) as u8).view(),
+        ]).unwrap()
+    }
+
+    /// Straightforward 2d convolution with the full kernel and a sorted window for the median,
+    /// kept as the baseline the fast paths are benchmarked against.
+    pub fn apply_reference(&self, img: &Array3<u8>) -> Array3<u8> {
+        let kernel = self.kernel();
+        self.blur_image(img, |channel, fill| match self.mode {
+            BlurMode::Median => self.median_blur_channel(channel, fill),
+            _ => convolve_bordered(channel, &kernel, &self.border, fill),
+        })
+    }
+
+    fn median_blur_channel(&self, channel: &Array2<f64>, fill: f64) -> Array2<f64> {
         let (width, height) = channel.dim();
         let mut res = Array2::<f64>::zeros((width, height));
 
         for x in 0..width as i32{
             for y in 0..height as i32 {
-                let mut vals = Vec::<f64>::new();
-
-                for i in -radius..radius {
-                    for j in -radius..radius {
-                        let x_ = (x + i).max(0).min(width as i32 - 1);
-                        let y_ = (y + j).max(0).min(height as i32 - 1);
-
-                        vals.push(channel[[x_ as usize, y_ as usize]]);
-                    }
-                }
+                let mut vals = array_slice(channel, x, y, self.radius, &self.border, fill).into_raw_vec();
                 res[[x as usize, y as usize]] = median(&mut vals);
             }
         }
@@ -88,46 +122,100 @@ impl Blur {
 
 impl Manipulate for Blur {
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
-        let (rc , gc, bc) = img.rgb_as_float();
-        let kernel = match self.mode {
-            BlurMode::Gaussian => outer_product(
-                &gaussian_kernel(self.diameter, self.sigma),
-                &gaussian_kernel(self.diameter, self.sigma),
-            ),
-            BlurMode::Box => Array2::<f64>::ones((self.diameter as usize, self.diameter as usize)) / (self.diameter * self.diameter) as f64,
-            BlurMode::Median => Array2::<f64>::ones((self.diameter as usize, self.diameter as usize)),
-        };
+        self.blur_image(img, |channel, fill| self.blur_channel(channel, fill))
+    }
 
-        let blur_fn = match self.mode {
-            BlurMode::Gaussian => Self::base_blur_channel,
-            BlurMode::Box => Self::base_blur_channel,
-            BlurMode::Median => Self::median_blur_channel,
-        };
+    fn details_str(&self) -> String {
+        format!("Blur -> radius: {}, mode: {:?}, border: {:?}", self.radius, self.mode, self.border)
+    }
 
-        stack(Axis(2), &[
-            blur_fn(&rc, &kernel, self.radius).mapv(|x| x.min(255.0).max(0.0) as u8).view(),
-            blur_fn(&gc, &kernel, self.radius).mapv(|x| x.min(255.0).max(0.0) as u8).view(),
-            blur_fn(&bc, &kernel, self.radius).mapv(|x| x.min(255.0).max(0.0) as u8).view(),
-        ]).unwrap()
+    // wrapped borders read the opposite edge of the image
+    fn neighborhood(&self) -> Option<usize> {
+        match self.mode {
+            _ if self.border == BorderMode::Wrap => None,
+            BlurMode::Gaussian if self.radius > ITERATED_BOX_RADIUS =>
+                Some(gaussian_boxes(self.sigma, GAUSSIAN_BOXES).iter().map(|width| *width as usize / 2).sum()),
+            _ => Some(self.radius as usize),
+        }
     }
 
-    fn details_str(&self) -> String {
-        format!("Blur -> radius: {}, mode: {:?}", self.radius, self.mode)
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        let radius = (self.radius as f64 * factor).round() as i32;
+        Some(Filter::Blur(Blur::new(radius, self.mode).with_border(self.border)))
     }
 }
 
+/// Also the parameters of the bench command.
+pub const RADIUS: Param = Param::required("radius", ParamKind::Int(0, 50), "3");
+pub const MODE: Param = Param::required("mode", ParamKind::Choice(&["gaussian", "box", "median"]), "gaussian");
+pub const BORDER: Param = Param::optional("border", ParamKind::Border, Some("clamp"), "reflect");
+
 impl CommandParse for Blur {
-    fn parse(command: Vec<String>) -> Result<Filter, Box<dyn std::error::Error>> {
-        let maybe_radius = match command.get(0) {
-            Some(s) => s,
-            None => "nan",
-        };
-        let maybe_mode = match command.get(1) {
-            Some(s) => s,
-            None => "nam",
-        };
-        let radius = maybe_radius.parse::<i32>()?;
-        let mode = maybe_mode.parse::<BlurMode>()?;
-        Ok(Filter::Blur(Blur::new(radius, mode)))
-    }
-}
\ No newline at end of file
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let radius = RADIUS.parse::<i32>("blur", arg(0))?;
+        let mode = MODE.parse::<BlurMode>("blur", arg(1))?;
+        let border = BORDER.parse::<BorderMode>("blur", arg(2))?;
+        Ok(Filter::Blur(Blur::new(radius, mode).with_border(border)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "blur", forms: &[Form { keywords: "", params: &[RADIUS, MODE, BORDER], description: "" }] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgarray::noise_image;
+
+    const BORDERS: [BorderMode; 5] = [
+        BorderMode::Clamp,
+        BorderMode::Reflect,
+        BorderMode::Mirror,
+        BorderMode::Wrap,
+        BorderMode::Constant([10, 200, 90]),
+    ];
+
+    fn max_difference(a: &Array3<u8>, b: &Array3<u8>) -> u8 {
+        a.iter().zip(b.iter()).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0)
+    }
+
+    // separable and running sum blurs only differ from the full kernel by rounding
+    #[test]
+    fn fast_paths_match_reference() {
+        let img = noise_image(37, 29, 39);
+        for mode in [BlurMode::Gaussian, BlurMode::Box] {
+            for radius in [0, 1, 4, 9] {
+                for border in BORDERS {
+                    let mut blur = Blur::new(radius, mode).with_border(border);
+                    let difference = max_difference(&blur.apply(&img), &blur.apply_reference(&img));
+                    assert!(difference <= 1, "{:?} radius {}, border {:?} differs by {}", mode, radius, border, difference);
+                }
+            }
+        }
+    }
+
+    // repeated box blurs only approximate the gaussian, most of the difference is around the borders
+    #[test]
+    fn iterated_box_approximates_gaussian() {
+        let img = noise_image(48, 36, 39);
+        for border in BORDERS {
+            let mut blur = Blur::new(ITERATED_BOX_RADIUS + 1, BlurMode::Gaussian).with_border(border);
+            let difference = max_difference(&blur.apply(&img), &blur.apply_reference(&img));
+            assert!(difference <= 12, "border {:?} differs by {}", border, difference);
+        }
+    }
+
+    // the sliding histogram has to give exactly what sorting every window gives
+    #[test]
+    fn histogram_median_matches_sorted_median() {
+        let img = noise_image(23, 17, 40);
+        for radius in [0, 1, 2, 5, 12] {
+            for border in BORDERS {
+                let mut blur = Blur::new(radius, BlurMode::Median).with_border(border);
+                assert_eq!(blur.apply(&img), blur.apply_reference(&img), "radius {}, border {:?}", radius, border);
+            }
+        }
+    }
+}
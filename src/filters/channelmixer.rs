@@ -0,0 +1,138 @@
+use ndarray::{Array2, Array3};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::filters::{sepia::SEPIA_MATRIX, huerotate::Huerotate};
+use crate::imgarray::AsImage;
+use crate::linalg::par_image_from_fn;
+
+/// Each row produces one output channel (red, green, blue) as r * m0 + g * m1 + b * m2 + m3.
+pub type ColorMatrix = [[f64; 4]; 3];
+
+pub const IDENTITY_MATRIX: ColorMatrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+];
+
+pub fn mix_channels(img: &Array3<u8>, matrix: &ColorMatrix) -> (Array2<f64>, Array2<f64>, Array2<f64>) {
+    let (r, g, b) = img.rgb_as_float();
+    let mix = |row: &[f64; 4]| &r * row[0] + &g * row[1] + &b * row[2] + row[3];
+    (mix(&matrix[0]), mix(&matrix[1]), mix(&matrix[2]))
+}
+
+// at most 4 decimal places, without trailing zeros
+fn format_coefficient(x: f64) -> String {
+    let s = format!("{:.4}", x);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    match s {
+        "-0" => "0".to_string(),
+        _ => s.to_string(),
+    }
+}
+
+pub struct ChannelMixer {
+    matrix: ColorMatrix,
+}
+
+impl ChannelMixer {
+    pub fn new(matrix: ColorMatrix) -> Self {
+        Self { matrix }
+    }
+
+    pub fn swap(channels: &str) -> Result<Self, String> {
+        let (first, second) = match channels {
+            "rg" | "gr" => (0, 1),
+            "rb" | "br" => (0, 2),
+            "gb" | "bg" => (1, 2),
+            _ => return Err(format!("{} is not a valid channel pair", channels)),
+        };
+        let mut matrix = IDENTITY_MATRIX;
+        matrix.swap(first, second);
+        Ok(Self { matrix })
+    }
+
+    pub fn mono(weights: [f64; 3]) -> Self {
+        let row = [weights[0], weights[1], weights[2], 0.0];
+        Self { matrix: [row, row, row] }
+    }
+
+    pub fn matrix(&self) -> ColorMatrix {
+        self.matrix
+    }
+}
+
+impl Manipulate for ChannelMixer {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let (width, height, _) = img.dim();
+        par_image_from_fn(width, height, |x, y| {
+            let (r, g, b) = (img[[x, y, 0]] as f64, img[[x, y, 1]] as f64, img[[x, y, 2]] as f64);
+            let mix = |row: &[f64; 4]| (r * row[0] + g * row[1] + b * row[2] + row[3]).clamp(0.0, 255.0).round() as u8;
+            [mix(&self.matrix[0]), mix(&self.matrix[1]), mix(&self.matrix[2])]
+        })
+    }
+
+    fn details_str(&self) -> String {
+        // printed in the same form the command accepts, so it can be copied back into 'add channelmixer'
+        let rows = self.matrix
+            .iter()
+            .map(|row| row.iter().map(|x| format_coefficient(*x)).collect::<Vec<String>>().join(","))
+            .collect::<Vec<String>>()
+            .join(" ");
+        format!("Channel mixer -> matrix: {}", rows)
+    }
+
+    fn neighborhood(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+const MODES: &str = "swap, sepia, huerotate, mono or 3 rows of coefficients";
+const RED_ROW: Param = Param::required("r row", ParamKind::Text("3 or 4 comma separated numbers r,g,b[,offset]"), "0.5,0.5,0");
+const GREEN_ROW: Param = Param::required("g row", ParamKind::Text("3 or 4 comma separated numbers r,g,b[,offset]"), "0,1,0");
+const BLUE_ROW: Param = Param::required("b row", ParamKind::Text("3 or 4 comma separated numbers r,g,b[,offset]"), "0,0.2,0.8,10");
+const PAIR: Param = Param::required("channels", ParamKind::Choice(&["rg", "rb", "gb"]), "rb");
+const WEIGHTS: Param = Param::required("weights", ParamKind::Text("3 comma separated numbers r,g,b"), "0.3,0.6,0.1");
+const DEGREES: Param = Param::required("degrees", ParamKind::Int(i32::MIN, i32::MAX), "90");
+
+impl CommandParse for ChannelMixer {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+
+        let mixer = match arg(0) {
+            "swap" => PAIR.parse_with("channelmixer", arg(1), ChannelMixer::swap)?,
+            "sepia" => ChannelMixer::new(SEPIA_MATRIX),
+            "huerotate" => ChannelMixer::new(Huerotate::new(DEGREES.parse::<i32>("channelmixer", arg(1))?).matrix()),
+            "mono" => {
+                let weights = WEIGHTS.parse_list::<f64>("channelmixer", arg(1), &[3])?;
+                ChannelMixer::mono([weights[0], weights[1], weights[2]])
+            },
+            "" => return Err(MagrustteError::missing_parameter("channelmixer", "mode", MODES)),
+            mode => {
+                if command.len() != 3 {
+                    return Err(MagrustteError::bad_parameter("channelmixer", "mode", mode, MODES));
+                }
+                let mut matrix = IDENTITY_MATRIX;
+                for (i, (row, param)) in matrix.iter_mut().zip([RED_ROW, GREEN_ROW, BLUE_ROW]).enumerate() {
+                    let coefficients = param.parse_list::<f64>("channelmixer", arg(i), &[3, 4])?;
+                    row[3] = 0.0;
+                    row[..coefficients.len()].copy_from_slice(&coefficients);
+                }
+                ChannelMixer::new(matrix)
+            },
+        };
+        Ok(Filter::ChannelMixer(mixer))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "channelmixer",
+            forms: &[
+                Form { keywords: "", params: &[RED_ROW, GREEN_ROW, BLUE_ROW], description: "each row mixes the channels into one output channel" },
+                Form { keywords: "swap", params: &[PAIR], description: "" },
+                Form { keywords: "mono", params: &[WEIGHTS], description: "" },
+                Form { keywords: "sepia", params: &[], description: "editable matrix preset" },
+                Form { keywords: "huerotate", params: &[DEGREES], description: "editable matrix preset" },
+            ],
+        }
+    }
+}
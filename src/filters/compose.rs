@@ -1,51 +1,249 @@
-use ndarray::Array3;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use ndarray::{Array3, s};
 use crate::filters::{Filter, Manipulate};
+use crate::imgarray::AsImage;
+use crate::progress::{Progress, Reporter};
 
+/// Filters applied one after the other, as a filter itself.
 #[derive(Default)]
 pub struct Compose {
     filters: Vec<Filter>,
+    /// First filter that has to be applied again, the ones before it are already part of the last result.
     pub rerender_index: usize,
+    /// Previews are cached separately, so showing a preview doesn't invalidate the full size render and vice versa.
+    pub preview_rerender_index: usize,
+    /// When set, filters that report a neighborhood are applied to tiles of this size to bound memory use.
+    pub tile_size: Option<usize>,
+    /// When set, full size renders also hash the output of every filter.
+    pub profiling: bool,
+    // one per filter, from the last time it was rendered at full size
+    reports: Vec<Option<StepReport>>,
+}
+
+/// How a filter of the pipeline performed, see [`Compose::reports`].
+#[derive(Clone, Copy)]
+pub struct StepReport {
+    pub time: Duration,
+    /// Bytes of the input, the output and the float copy filters work on, estimated rather than measured.
+    pub memory: usize,
+    pub hash: Option<u64>,
+}
+
+fn memory_estimate(img: &Array3<u8>, res: &Array3<u8>, working_pixels: usize) -> usize {
+    img.len() + res.len() + working_pixels * 3 * std::mem::size_of::<f64>()
+}
+
+/// Smallest accepted tile size, smaller tiles would mostly process their overlaps.
+pub const MIN_TILE_SIZE: usize = 64;
+
+// applies the filter tile by tile, every tile is extended by the filter's neighborhood so that the results
+// match processing the whole image, filters that need the whole image still get it
+fn apply_tiled(filter: &mut Filter, img: &Array3<u8>, tile_size: usize) -> Array3<u8> {
+    let radius = match filter.neighborhood() {
+        Some(radius) => radius,
+        None => return filter.apply(img),
+    };
+    let (width, height, _) = img.dim();
+    let mut res = Array3::<u8>::zeros((width, height, 3));
+
+    let tiles = Reporter::current().start_tiles(width.div_ceil(tile_size) * height.div_ceil(tile_size));
+    for x0 in (0..width).step_by(tile_size) {
+        for y0 in (0..height).step_by(tile_size) {
+            if tiles.is_cancelled() {
+                break;
+            }
+            let (x1, y1) = ((x0 + tile_size).min(width), (y0 + tile_size).min(height));
+            let (ext_x0, ext_y0) = (x0.saturating_sub(radius), y0.saturating_sub(radius));
+            let (ext_x1, ext_y1) = ((x1 + radius).min(width), (y1 + radius).min(height));
+
+            let tile = tiles.clone().run(|| filter.apply(&img.slice(s![ext_x0..ext_x1, ext_y0..ext_y1, ..]).to_owned()));
+            res.slice_mut(s![x0..x1, y0..y1, ..])
+                .assign(&tile.slice(s![x0 - ext_x0..x1 - ext_x0, y0 - ext_y0..y1 - ext_y0, ..]));
+            tiles.tile_done();
+        }
+    }
+    res
+}
+
+/// Builds a [`Compose`] filter by filter.
+#[derive(Default)]
+pub struct ComposeBuilder {
+    filters: Vec<Filter>,
+    tile_size: Option<usize>,
+    profiling: bool,
+}
+
+impl ComposeBuilder {
+    /// Appends a filter, filters are applied in the order they were added.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Applies filters that only look at nearby pixels in tiles of this size, at least [`MIN_TILE_SIZE`].
+    pub fn tile_size(mut self, tile_size: usize) -> Self {
+        self.tile_size = Some(tile_size.max(MIN_TILE_SIZE));
+        self
+    }
+
+    /// Hashes the output of every filter, see [`Compose::reports`].
+    pub fn profiling(mut self, profiling: bool) -> Self {
+        self.profiling = profiling;
+        self
+    }
+
+    pub fn build(self) -> Compose {
+        let mut compose = Compose::new(self.filters);
+        compose.tile_size = self.tile_size;
+        compose.profiling = self.profiling;
+        compose
+    }
 }
 
 impl Compose {
+    pub fn builder() -> ComposeBuilder {
+        ComposeBuilder::default()
+    }
+
     pub fn new(filters_vec: Vec<Filter>) -> Self {
-        Self { filters: filters_vec, rerender_index: 0 }
+        let reports = vec![None; filters_vec.len()];
+        Self { filters: filters_vec, rerender_index: 0, preview_rerender_index: 0, tile_size: None, profiling: false, reports }
+    }
+
+    /// Removes all filters, keeping the settings.
+    pub fn clear(&mut self) {
+        self.filters.clear();
+        self.reports.clear();
+        self.rerender_index = 0;
+        self.preview_rerender_index = 0;
     }
 
     pub fn add(&mut self, filter: Filter) {
         self.filters.append(&mut vec![filter]);
+        self.reports.push(None);
     }
 
     pub fn remove(&mut self, index: usize) {
         self.filters.remove(index);
+        self.reports.remove(index);
         if index < self.rerender_index {
             self.rerender_index = 0;
         } // no need to rerender if we removed a filter that hasn't been applied yet
+        if index < self.preview_rerender_index {
+            self.preview_rerender_index = 0;
+        }
     }
 
     pub fn len(&self) -> usize {
         self.filters.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Applies only the filters added since the last call to prev_result, which that call returned, or every filter to
+    /// img when nothing was rendered yet or a filter that was already applied has been removed since.
+    pub fn apply_incremental(&mut self, img: &Array3<u8>, prev_result: &Array3<u8>, progress: &Arc<Progress>) -> Option<Array3<u8>> {
+        let base = if self.rerender_index == 0 { img } else { prev_result };
+        let res = self.apply_from(base, self.rerender_index, 1.0, Some(progress))?;
+        self.rerender_index = self.filters.len();
+        Some(res)
+    }
+
+    /// Same as apply_incremental for a downscaled image, filters measured in pixels are scaled by factor to look the same.
+    pub fn apply_preview(&mut self, img: &Array3<u8>, prev_result: &Array3<u8>, factor: f64, progress: &Arc<Progress>) -> Option<Array3<u8>> {
+        let base = if self.preview_rerender_index == 0 { img } else { prev_result };
+        let res = self.apply_from(base, self.preview_rerender_index, factor, Some(progress))?;
+        self.preview_rerender_index = self.filters.len();
+        Some(res)
+    }
+
+    // None when the progress was cancelled, the rerender index is left as it was so the cached image stays valid
+    fn apply_from(&mut self, img: &Array3<u8>, start: usize, factor: f64, progress: Option<&Arc<Progress>>) -> Option<Array3<u8>> {
+        let mut res = img.clone();
+        let (tile_size, count) = (self.tile_size, self.filters.len());
+        for (i, filter) in self.filters.iter_mut().enumerate().skip(start) {
+            let mut scaled = if factor != 1.0 { filter.scaled(factor) } else { None };
+            let filter = scaled.as_mut().unwrap_or(filter);
+            if let Some(progress) = progress {
+                let details = filter.details_str();
+                let name = details.split(" ->").next().unwrap_or_default();
+                progress.start(&format!("{} ({}/{})", name, i + 1, count));
+            }
+            let start = Instant::now();
+            let mut apply = || match tile_size {
+                Some(tile_size) => apply_tiled(filter, &res, tile_size),
+                None => filter.apply(&res),
+            };
+            let filtered = match progress {
+                Some(progress) => progress.run(apply),
+                None => apply(),
+            };
+            if progress.is_some_and(|progress| progress.is_cancelled()) {
+                return None;
+            }
+            if factor == 1.0 {
+                let time = start.elapsed();
+                let pixels = res.len().max(filtered.len()) / 3;
+                let working_pixels = match (tile_size, filter.neighborhood()) {
+                    (Some(tile_size), Some(radius)) => (tile_size + 2 * radius).pow(2).min(pixels),
+                    _ => pixels,
+                };
+                let hash = if self.profiling { Some(filtered.content_hash()) } else { None };
+                self.reports[i] = Some(StepReport { time, memory: memory_estimate(&res, &filtered, working_pixels), hash });
+            }
+            res = filtered;
+        }
+        Some(res)
+    }
+
+    /// Measurements of every filter from the last time it was rendered at full size.
+    pub fn reports(&self) -> &[Option<StepReport>] {
+        &self.reports
+    }
+
+    /// Time, memory and output hash of every filter, followed by the totals.
+    pub fn profile_str(&self) -> String {
+        let mut lines = vec![format!("{:<5} {:>9} {:>10} {:<16} filter", "step", "time", "memory", "hash")];
+        for (i, (filter, report)) in self.filters.iter().zip(&self.reports).enumerate() {
+            let (time, memory, hash) = match report {
+                Some(report) => (
+                    format!("{:.3}s", report.time.as_secs_f64()),
+                    format!("{:.1} MB", report.memory as f64 / 1e6),
+                    report.hash.map_or("-".to_string(), |hash| format!("{:016x}", hash)),
+                ),
+                None => ("-".to_string(), "-".to_string(), "-".to_string()),
+            };
+            lines.push(format!("{:<5} {:>9} {:>10} {:<16} {}", i, time, memory, hash, filter.details_str()));
+        }
+        let total = self.reports.iter().flatten().map(|report| report.time).sum::<Duration>();
+        let peak = self.reports.iter().flatten().map(|report| report.memory).max().unwrap_or(0);
+        lines.push(format!("{:<5} {:>9} {:>10}", "total", format!("{:.3}s", total.as_secs_f64()), format!("{:.1} MB", peak as f64 / 1e6)));
+        lines.join("\n")
+    }
 }
 
+// every filter is applied to the given image, the incremental renders of the app keep their own cache
 impl Manipulate for Compose {
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        // renders without a progress can't be cancelled
+        self.apply_from(img, 0, 1.0, None).unwrap_or_else(|| img.clone())
+    }
 
-        let mut res = img.clone();
-        self.filters.iter_mut().enumerate().for_each(|(i, filter)| {
-            if i >= self.rerender_index {
-                res = filter.apply(&res);
-            }
-        });
-        self.rerender_index = self.filters.len();
-        res
+    fn apply_with_progress(&mut self, img: &Array3<u8>, progress: &Arc<Progress>) -> Option<Array3<u8>> {
+        self.apply_from(img, 0, 1.0, Some(progress))
     }
 
     fn details_str(&self) -> String {
         let details = self.filters
             .iter()
             .enumerate()
-            .map(|(i, filter)| format!("{} {}", i, filter.details_str()))
+            .map(|(i, filter)| match self.reports[i] {
+                Some(report) => format!("{} {} ({:.3}s)", i, filter.details_str(), report.time.as_secs_f64()),
+                None => format!("{} {}", i, filter.details_str()),
+            })
             .collect::<Vec<String>>()
             .join("\n");
         match details.len() {
@@ -53,4 +251,49 @@ impl Manipulate for Compose {
             _ => details,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::MagrustteError;
+    use crate::filters::PARSERS;
+    use crate::filters::blur::{Blur, BlurMode};
+    use crate::filters::invert::Invert;
+    use crate::imgarray::noise_image;
+
+    // every filter reporting a neighborhood, configured as in the examples of 'help <filter>', so filters
+    // added later are covered as well, the tiles are small and don't divide the image evenly
+    #[test]
+    fn tiled_matches_whole_image() {
+        let img = noise_image(150, 110, 43);
+        for parser in PARSERS {
+            for example in (parser.usage)().examples() {
+                let args = example.split_whitespace().skip(1).map(|s| s.to_string()).collect();
+                let mut filter = match (parser.parse)(args) {
+                    Ok(filter) => filter,
+                    // examples reading a file, e.g. convolve kernels
+                    Err(MagrustteError::Io { .. }) => continue,
+                    Err(e) => panic!("'{}' doesn't parse: {}", example, e),
+                };
+                if filter.neighborhood().is_some() {
+                    assert!(apply_tiled(&mut filter, &img, 32) == filter.apply(&img), "'{}' differs when tiled", example);
+                }
+            }
+        }
+    }
+
+    // a pipeline built once can be applied to any number of images
+    #[test]
+    fn pipeline_applies_to_every_image() {
+        let mut pipeline = Compose::builder()
+            .filter(Filter::Blur(Blur::new(2, BlurMode::Gaussian)))
+            .filter(Filter::Invert(Invert::new()))
+            .build();
+        for seed in [44, 45] {
+            let img = noise_image(40, 30, seed);
+            let expected = Invert::new().apply(&Blur::new(2, BlurMode::Gaussian).apply(&img));
+            assert!(pipeline.apply(&img) == expected, "image {} isn't filtered", seed);
+        }
+    }
+}
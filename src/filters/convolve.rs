@@ -0,0 +1,165 @@
+use ndarray::{Array2, Array3, stack, Axis};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::imgarray::AsImage;
+use crate::linalg::{BorderMode, convolve_bordered};
+
+pub struct Convolve {
+    kernel: Array2<f64>,
+    normalize: bool,
+    bias: f64,
+    border: BorderMode,
+}
+
+impl Convolve {
+    /// Values are given row by row, as the kernel is usually written down.
+    pub fn new(width: usize, height: usize, values: &[f64]) -> Result<Self, MagrustteError> {
+        if width.is_multiple_of(2) || height.is_multiple_of(2) {
+            return Err(MagrustteError::bad_parameter("convolve", "kernel size", &format!("{}x{}", width, height), "odd dimensions like 3x3"));
+        }
+        if values.len() != width * height {
+            let expected = format!("{} values for a {}x{} kernel", width * height, width, height);
+            return Err(MagrustteError::bad_parameter("convolve", "kernel", &format!("{} values", values.len()), &expected));
+        }
+        // images are indexed x first, hence the transposition
+        let kernel = Array2::from_shape_fn((width, height), |(x, y)| values[y * width + x]);
+        Ok(Self { kernel, normalize: false, bias: 0.0, border: BorderMode::Clamp })
+    }
+
+    /// One kernel row per line, values separated by commas or whitespace.
+    pub fn from_file(path: &str) -> Result<Self, MagrustteError> {
+        let content = std::fs::read_to_string(path).map_err(|source| MagrustteError::Io { path: path.to_string(), source })?;
+        let bad_file = |expected: &str| MagrustteError::bad_parameter("convolve", "kernel file", path, expected);
+        let rows = content.lines()
+            .map(|line| line.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|s| !s.is_empty())
+                // same as the inline kernel, inf and nan would spread over the whole image
+                .map(|s| s.parse::<f64>().ok().filter(|v| v.is_finite()))
+                .collect::<Option<Vec<f64>>>())
+            .collect::<Option<Vec<Vec<f64>>>>()
+            .ok_or_else(|| bad_file("rows of finite numbers separated by commas or whitespace"))?
+            .into_iter()
+            .filter(|row| !row.is_empty())
+            .collect::<Vec<Vec<f64>>>();
+
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            return Err(bad_file("rows of the same length"));
+        }
+        Self::new(width, rows.len(), &rows.concat())
+    }
+
+    pub fn with_options(mut self, normalize: bool, bias: f64, border: BorderMode) -> Self {
+        self.normalize = normalize;
+        self.bias = bias;
+        self.border = border;
+        self
+    }
+}
+
+impl Manipulate for Convolve {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let (rc, gc, bc) = img.rgb_as_float();
+        // kernels summing to zero (edge detection, outlines) are left as they are
+        let sum = self.kernel.sum();
+        let kernel = if self.normalize && sum != 0.0 { &self.kernel / sum } else { self.kernel.clone() };
+
+        let convolve_fn = |channel: &Array2<f64>, c: usize| convolve_bordered(channel, &kernel, &self.border, self.border.fill(c))
+            .mapv(|x| (x + self.bias).clamp(0.0, 255.0).round() as u8);
+
+        stack(Axis(2), &[
+            convolve_fn(&rc, 0).view(),
+            convolve_fn(&gc, 1).view(),
+            convolve_fn(&bc, 2).view(),
+        ]).unwrap()
+    }
+
+    fn details_str(&self) -> String {
+        let (width, height) = self.kernel.dim();
+        let values = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| self.kernel[[x, y]].to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        let normalize = if self.normalize { ", normalized" } else { "" };
+        format!("Convolve -> kernel: {}x{} {}{}, bias: {}, border: {:?}", width, height, values, normalize, self.bias, self.border)
+    }
+
+    fn neighborhood(&self) -> Option<usize> {
+        match self.border {
+            BorderMode::Wrap => None,
+            _ => Some(self.kernel.dim().0.max(self.kernel.dim().1) / 2),
+        }
+    }
+}
+
+const SIZE: Param = Param::required("size", ParamKind::Text("odd dimensions like 3x3"), "3x3");
+const KERNEL: Param = Param::required("kernel", ParamKind::Text("width times height comma separated numbers"), "0,-1,0,-1,5,-1,0,-1,0");
+const PATH: Param = Param::required("path", ParamKind::Text("a file with one kernel row per line"), "kernel.txt");
+const NORMALIZE: Param = Param::keyword("normalize", &[]);
+const BIAS_VALUE: Param = Param::required("bias value", ParamKind::Number(f64::NEG_INFINITY, f64::INFINITY), "128");
+const BIAS: Param = Param::keyword("bias", &[BIAS_VALUE]);
+const BORDER_MODE: Param = Param::required("border mode", ParamKind::Border, "reflect");
+const BORDER: Param = Param::keyword("border", &[BORDER_MODE]);
+
+impl CommandParse for Convolve {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let mut args = command.iter().map(|s| s.as_str()).filter(|s| !s.is_empty());
+
+        let convolve = match args.next().unwrap_or("") {
+            "file" => Convolve::from_file(&PATH.parse::<String>("convolve", args.next().unwrap_or(""))?)?,
+            size => {
+                let (width, height) = SIZE.parse_with("convolve", size, |s| {
+                    let (width, height) = s.split_once('x').ok_or(())?;
+                    Ok::<_, ()>((width.parse::<usize>().map_err(|_| ())?, height.parse::<usize>().map_err(|_| ())?))
+                })?;
+                // the count is known by now, so the error can say exactly how many values were expected
+                let expected = format!("{} comma separated numbers", width * height);
+                let values = KERNEL.parse_list::<f64>("convolve", args.next().unwrap_or(""), &[width * height]).map_err(|e| match e {
+                    MagrustteError::MissingParameter { .. } => MagrustteError::missing_parameter("convolve", KERNEL.name, &expected),
+                    MagrustteError::BadParameter { value, .. } => MagrustteError::bad_parameter("convolve", KERNEL.name, &value, &expected),
+                    e => e,
+                })?;
+                Convolve::new(width, height, &values)?
+            },
+        };
+
+        let (mut normalize, mut bias, mut border) = (false, 0.0, BorderMode::Clamp);
+        while let Some(option) = args.next() {
+            match option {
+                "normalize" => normalize = true,
+                "bias" => bias = BIAS_VALUE.parse::<f64>("convolve", args.next().unwrap_or(""))?,
+                "border" => border = BORDER_MODE.parse::<BorderMode>("convolve", args.next().unwrap_or(""))?,
+                _ => return Err(MagrustteError::unexpected_argument("convolve", option)),
+            }
+        }
+
+        Ok(Filter::Convolve(convolve.with_options(normalize, bias, border)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "convolve",
+            forms: &[
+                Form { keywords: "", params: &[SIZE, KERNEL, NORMALIZE, BIAS, BORDER], description: "custom kernel given row by row" },
+                Form { keywords: "file", params: &[PATH, NORMALIZE, BIAS, BORDER], description: "custom kernel read from a file" },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_files_need_finite_numbers() {
+        let path = std::env::temp_dir().join("magrustte_kernel.txt");
+        let path = path.to_str().unwrap();
+        for (content, valid) in [("0 1 0\n1, -4, 1\n0 1 0\n", true), ("0 1 0\n1 inf 1\n0 1 0", false), ("NaN", false), ("1 2\n3", false)] {
+            std::fs::write(path, content).unwrap();
+            assert_eq!(Convolve::from_file(path).is_ok(), valid, "{:?}", content);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use ndarray::{Array3, s};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+
+pub struct Crop {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Crop {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x: x.max(0) as usize,
+            y: y.max(0) as usize,
+            width: width.max(1) as usize,
+            height: height.max(1) as usize,
+        }
+    }
+}
+
+impl Manipulate for Crop {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        // earlier filters may have changed the image size, so the region is fitted at render time
+        // and an empty image stays empty
+        let (width, height, _) = img.dim();
+        let x = self.x.min(width.saturating_sub(1));
+        let y = self.y.min(height.saturating_sub(1));
+        let x_end = (x + self.width).min(width);
+        let y_end = (y + self.height).min(height);
+        img.slice(s![x..x_end, y..y_end, ..]).to_owned()
+    }
+
+    fn details_str(&self) -> String {
+        format!("Crop -> x: {}, y: {}, width: {}, height: {}", self.x, self.y, self.width, self.height)
+    }
+
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        let scale = |v: usize| (v as f64 * factor).round() as i32;
+        Some(Filter::Crop(Crop::new(scale(self.x), scale(self.y), scale(self.width), scale(self.height))))
+    }
+}
+
+const X: Param = Param::required("x", ParamKind::Int(0, i32::MAX), "100");
+const Y: Param = Param::required("y", ParamKind::Int(0, i32::MAX), "50");
+const WIDTH: Param = Param::required("width", ParamKind::Int(1, i32::MAX), "640");
+const HEIGHT: Param = Param::required("height", ParamKind::Int(1, i32::MAX), "480");
+
+impl CommandParse for Crop {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let x = X.parse::<i32>("crop", arg(0))?;
+        let y = Y.parse::<i32>("crop", arg(1))?;
+        let width = WIDTH.parse::<i32>("crop", arg(2))?;
+        let height = HEIGHT.parse::<i32>("crop", arg(3))?;
+        Ok(Filter::Crop(Crop::new(x, y, width, height)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "crop", forms: &[Form { keywords: "", params: &[X, Y, WIDTH, HEIGHT], description: "" }] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgarray::noise_image;
+
+    #[test]
+    fn region_is_fitted_to_image() {
+        let img = noise_image(30, 20, 31);
+        let res = Crop::new(5, 4, 10, 8).apply(&img);
+        assert_eq!(res.dim(), (10, 8, 3));
+        assert_eq!(res, img.slice(s![5..15, 4..12, ..]));
+        // regions past the edges keep what is left of them, at least the last row and column
+        assert_eq!(Crop::new(25, 15, 10, 10).apply(&img).dim(), (5, 5, 3));
+        assert_eq!(Crop::new(40, 30, 10, 10).apply(&img).dim(), (1, 1, 3));
+        assert_eq!(Crop::new(3, 3, 10, 10).apply(&Array3::zeros((0, 0, 3))).dim(), (0, 0, 3));
+    }
+}
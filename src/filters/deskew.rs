@@ -0,0 +1,95 @@
+use ndarray::Array3;
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::filters::{autocrop::border_color, grayscale::Grayscale, rotate::{Rotate, RotateMode}, threshold::Threshold};
+use crate::linalg::sobel;
+
+pub struct Deskew {
+    max_angle: f64,
+    mode: RotateMode,
+    detected_angle: Option<f64>,
+}
+
+const ANGLE_STEP: f64 = 0.1;
+// edge points beyond this are subsampled, the estimate doesn't improve noticeably with more
+const MAX_POINTS: usize = 50000;
+
+impl Deskew {
+    pub fn new(max_angle: f64, mode: RotateMode) -> Self {
+        Self { max_angle: max_angle.clamp(ANGLE_STEP, 45.0), mode, detected_angle: None }
+    }
+
+    /// Clockwise skew of the dominant lines in degrees, found with a Hough transform of the edge points.
+    /// <https://en.wikipedia.org/wiki/Hough_transform>
+    pub fn estimate_angle(&self, img: &Array3<u8>) -> f64 {
+        let (gx, gy) = sobel(&Grayscale::new().luma(img));
+        let magnitude = (&gx * &gx + &gy * &gy).mapv(f64::sqrt);
+        let max = magnitude.iter().cloned().fold(0.0, f64::max).max(f64::EPSILON);
+        let level = Threshold::otsu_level(&magnitude.mapv(|x| x / max * 255.0)) as f64 / 255.0 * max;
+
+        let points = magnitude.indexed_iter()
+            .filter(|(_, &m)| m > level)
+            .map(|((x, y), _)| (x as f64, y as f64))
+            .collect::<Vec<(f64, f64)>>();
+        let stride = (points.len() / MAX_POINTS).max(1);
+        let points = points.into_iter().step_by(stride).collect::<Vec<(f64, f64)>>();
+
+        let (width, height, _) = img.dim();
+        let diagonal = ((width * width + height * height) as f64).sqrt().ceil() as usize;
+        let steps = (self.max_angle / ANGLE_STEP).round() as i32;
+
+        // straight lines pile up in few accumulator bins, so the sum of squared bin counts peaks at their angle
+        // horizontal and vertical lines are scored together, their normals are at 90 and 0 degrees when level
+        let mut best = (0.0, f64::MIN);
+        for step in -steps..=steps {
+            let angle = step as f64 * ANGLE_STEP;
+            let mut score = 0.0;
+            for normal in [90.0 + angle, angle] {
+                let (sin, cos) = normal.to_radians().sin_cos();
+                let mut accumulator = vec![0.0; 2 * diagonal + 1];
+                for &(x, y) in &points {
+                    let rho = (x * cos + y * sin).round() as i64 + diagonal as i64;
+                    accumulator[rho as usize] += 1.0;
+                }
+                score += accumulator.iter().map(|n| n * n).sum::<f64>();
+            }
+            if score > best.1 {
+                best = (angle, score);
+            }
+        }
+        best.0
+    }
+}
+
+impl Manipulate for Deskew {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let angle = self.estimate_angle(img);
+        self.detected_angle = Some(angle);
+        // uncovered corners are filled with the border color, so a following autocrop can remove them
+        Rotate::new(-angle, self.mode, border_color(img)).apply(img)
+    }
+
+    fn details_str(&self) -> String {
+        let detected = match self.detected_angle {
+            Some(angle) => format!(", detected skew: {:.1} degrees", angle),
+            None => "".to_string(),
+        };
+        format!("Deskew -> max angle: {}, mode: {:?}{}", self.max_angle, self.mode, detected)
+    }
+}
+
+const MAX_ANGLE: Param = Param::optional("max angle", ParamKind::Number(ANGLE_STEP, 45.0), Some("15"), "5");
+const MODE: Param = Param::optional("mode", ParamKind::Choice(&["expand", "crop"]), Some("expand"), "crop");
+
+impl CommandParse for Deskew {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let max_angle = MAX_ANGLE.parse::<f64>("deskew", arg(0))?;
+        let mode = MODE.parse::<RotateMode>("deskew", arg(1))?;
+        Ok(Filter::Deskew(Deskew::new(max_angle, mode)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "deskew", forms: &[Form { keywords: "", params: &[MAX_ANGLE, MODE], description: "straightens slightly rotated scans" }] }
+    }
+}
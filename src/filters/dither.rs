@@ -0,0 +1,280 @@
+use std::sync::OnceLock;
+use ndarray::{Array2, Array3};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::color::Palette;
+use crate::linalg::par_image_from_fn;
+use crate::progress::Reporter;
+
+pub struct Dither {
+    method: DitherMethod,
+    palette: Palette,
+}
+
+#[derive(Debug)]
+pub enum DitherMethod {
+    FloydSteinberg,
+    Atkinson,
+    Sierra,
+    Bayer(usize),
+    BlueNoise,
+}
+
+impl std::str::FromStr for DitherMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "floyd" | "floydsteinberg" => Ok(DitherMethod::FloydSteinberg),
+            "atkinson" => Ok(DitherMethod::Atkinson),
+            "sierra" => Ok(DitherMethod::Sierra),
+            "bayer2" => Ok(DitherMethod::Bayer(2)),
+            "bayer4" => Ok(DitherMethod::Bayer(4)),
+            "bayer8" => Ok(DitherMethod::Bayer(8)),
+            "bluenoise" => Ok(DitherMethod::BlueNoise),
+            _ => Err(format!("{} is not a valid dithering method", s)),
+        }
+    }
+}
+
+// error diffusion kernels as (dx, dy, weight), with the weights divided by the divisor
+// https://tannerhelland.com/2012/12/28/dithering-eleven-algorithms-source-code.html
+const FLOYD_STEINBERG: (&[(i32, i32, f64)], f64) = (
+    &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+    16.0,
+);
+// Atkinson intentionally diffuses only 6/8 of the error
+const ATKINSON: (&[(i32, i32, f64)], f64) = (
+    &[(1, 0, 1.0), (2, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0), (0, 2, 1.0)],
+    8.0,
+);
+const SIERRA: (&[(i32, i32, f64)], f64) = (
+    &[
+        (1, 0, 5.0), (2, 0, 3.0),
+        (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+        (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+    ],
+    32.0,
+);
+
+const BLUE_NOISE_SIZE: usize = 64;
+// takes a while to build, so it's built once and shared by every dither
+static BLUE_NOISE: OnceLock<Array2<f64>> = OnceLock::new();
+
+impl Dither {
+    pub fn new(method: DitherMethod, palette: Palette) -> Self {
+        Self { method, palette }
+    }
+
+    fn error_diffusion(&self, img: &Array3<u8>, kernel: &[(i32, i32, f64)], divisor: f64) -> Array3<u8> {
+        let (width, height, _) = img.dim();
+        let mut buffer = img.mapv(|x| x as f64);
+        let mut res = Array3::<u8>::zeros((width, height, 3));
+
+        // errors flow into the next rows, so rows are processed one after the other
+        let reporter = Reporter::current();
+        reporter.start_pass(height);
+        for y in 0..height {
+            if reporter.is_cancelled() {
+                break;
+            }
+            for x in 0..width {
+                let old = [buffer[[x, y, 0]], buffer[[x, y, 1]], buffer[[x, y, 2]]];
+                let new = self.palette.nearest(&old);
+
+                for c in 0..3 {
+                    res[[x, y, c]] = new[c];
+                    let error = old[c] - new[c] as f64;
+                    for &(dx, dy, weight) in kernel {
+                        let (x_, y_) = (x as i32 + dx, y as i32 + dy);
+                        if x_ >= 0 && x_ < width as i32 && y_ < height as i32 {
+                            buffer[[x_ as usize, y_ as usize, c]] += error * weight / divisor;
+                        }
+                    }
+                }
+            }
+            reporter.line_done();
+        }
+        res
+    }
+
+    // threshold map values lie in [-0.5, 0.5) and are scaled by the distance between palette colors
+    fn ordered(&self, img: &Array3<u8>, threshold_map: &Array2<f64>) -> Array3<u8> {
+        let (width, height, _) = img.dim();
+        let (map_width, map_height) = threshold_map.dim();
+        let spread = self.palette.spread();
+
+        par_image_from_fn(width, height, |x, y| {
+            let offset = threshold_map[[x % map_width, y % map_height]] * spread;
+            let color = [
+                img[[x, y, 0]] as f64 + offset,
+                img[[x, y, 1]] as f64 + offset,
+                img[[x, y, 2]] as f64 + offset,
+            ];
+            self.palette.nearest(&color)
+        })
+    }
+
+    /// <https://en.wikipedia.org/wiki/Ordered_dithering#Threshold_map>
+    pub fn bayer_matrix(size: usize) -> Array2<f64> {
+        let mut matrix = Array2::<f64>::zeros((1, 1));
+        while matrix.dim().0 < size {
+            let n = matrix.dim().0;
+            let mut next = Array2::<f64>::zeros((n * 2, n * 2));
+            for ((x, y), &v) in matrix.indexed_iter() {
+                next[[x, y]] = 4.0 * v;
+                next[[x + n, y]] = 4.0 * v + 2.0;
+                next[[x, y + n]] = 4.0 * v + 3.0;
+                next[[x + n, y + n]] = 4.0 * v + 1.0;
+            }
+            matrix = next;
+        }
+        let cells = (size * size) as f64;
+        matrix.mapv(|v| (v + 0.5) / cells - 0.5)
+    }
+
+    /// Void-and-cluster method, Ulichney 1993.
+    /// <https://cv.ulichney.com/papers/1993-void-cluster.pdf>
+    pub fn blue_noise_matrix(size: usize) -> Array2<f64> {
+        let cells = size * size;
+        let sigma: f64 = 1.5;
+
+        // energy contributed by a point at a toroidal offset
+        let mut falloff = Array2::<f64>::zeros((size, size));
+        for ((dx, dy), v) in falloff.indexed_iter_mut() {
+            let dx = dx.min(size - dx) as f64;
+            let dy = dy.min(size - dy) as f64;
+            *v = (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+        }
+
+        let update = |energy: &mut Array2<f64>, x: usize, y: usize, sign: f64| {
+            for ((i, j), v) in energy.indexed_iter_mut() {
+                *v += sign * falloff[[(i + size - x) % size, (j + size - y) % size]];
+            }
+        };
+        let find = |energy: &Array2<f64>, pattern: &Array2<bool>, value: bool, tightest: bool| {
+            energy.indexed_iter()
+                .filter(|(idx, _)| pattern[*idx] == value)
+                .fold(None, |best: Option<((usize, usize), f64)>, (idx, &e)| match best {
+                    Some((_, best_e)) if (tightest && e <= best_e) || (!tightest && e >= best_e) => best,
+                    _ => Some((idx, e)),
+                })
+                .unwrap()
+                .0
+        };
+
+        // deterministic pseudo random initial pattern with about a tenth of the cells set
+        let mut pattern = Array2::<bool>::from_elem((size, size), false);
+        let mut energy = Array2::<f64>::zeros((size, size));
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut ones = 0;
+        while ones < cells / 10 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let (x, y) = ((seed % size as u64) as usize, ((seed >> 32) % size as u64) as usize);
+            if !pattern[[x, y]] {
+                pattern[[x, y]] = true;
+                update(&mut energy, x, y, 1.0);
+                ones += 1;
+            }
+        }
+
+        // spread the initial points out evenly, this converges well before the iteration cap
+        for _ in 0..cells {
+            let (cx, cy) = find(&energy, &pattern, true, true);
+            pattern[[cx, cy]] = false;
+            update(&mut energy, cx, cy, -1.0);
+            let (vx, vy) = find(&energy, &pattern, false, false);
+            pattern[[vx, vy]] = true;
+            update(&mut energy, vx, vy, 1.0);
+            if (cx, cy) == (vx, vy) {
+                break;
+            }
+        }
+
+        let mut rank = Array2::<f64>::zeros((size, size));
+        let (initial_pattern, initial_energy) = (pattern.clone(), energy.clone());
+
+        // ranks below the initial point count, removing the tightest clusters first
+        for r in (0..ones).rev() {
+            let (x, y) = find(&energy, &pattern, true, true);
+            pattern[[x, y]] = false;
+            update(&mut energy, x, y, -1.0);
+            rank[[x, y]] = r as f64;
+        }
+
+        // remaining ranks, filling the largest voids first
+        let (mut pattern, mut energy) = (initial_pattern, initial_energy);
+        for r in ones..cells {
+            let (x, y) = find(&energy, &pattern, false, false);
+            pattern[[x, y]] = true;
+            update(&mut energy, x, y, 1.0);
+            rank[[x, y]] = r as f64;
+        }
+
+        rank.mapv(|r| (r + 0.5) / cells as f64 - 0.5)
+    }
+}
+
+impl Manipulate for Dither {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        match self.method {
+            DitherMethod::FloydSteinberg => self.error_diffusion(img, FLOYD_STEINBERG.0, FLOYD_STEINBERG.1),
+            DitherMethod::Atkinson => self.error_diffusion(img, ATKINSON.0, ATKINSON.1),
+            DitherMethod::Sierra => self.error_diffusion(img, SIERRA.0, SIERRA.1),
+            DitherMethod::Bayer(size) => self.ordered(img, &Self::bayer_matrix(size)),
+            DitherMethod::BlueNoise => self.ordered(img, BLUE_NOISE.get_or_init(|| Self::blue_noise_matrix(BLUE_NOISE_SIZE))),
+        }
+    }
+
+    fn details_str(&self) -> String {
+        format!("Dither -> method: {:?}, palette: {}", self.method, self.palette.name)
+    }
+}
+
+const METHOD: Param = Param::required(
+    "method",
+    ParamKind::Choice(&["floyd", "atkinson", "sierra", "bayer2", "bayer4", "bayer8", "bluenoise"]),
+    "floyd",
+);
+const PALETTE: Param = Param::optional(
+    "palette",
+    ParamKind::Text("bw, gray<levels>, websafe or at least 2 comma separated colors"),
+    Some("bw"),
+    "#000000,#ff8800,#ffffff",
+);
+
+impl CommandParse for Dither {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let method = METHOD.parse::<DitherMethod>("dither", arg(0))?;
+        let palette = PALETTE.parse::<Palette>("dither", arg(1))?;
+        Ok(Filter::Dither(Dither::new(method, palette)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "dither", forms: &[Form { keywords: "", params: &[METHOD, PALETTE], description: "" }] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // thresholds are (rank + 0.5) / n² - 0.5, every rank has to appear exactly once
+    fn ranks(matrix: &Array2<f64>) -> Vec<usize> {
+        let cells = matrix.len() as f64;
+        let mut ranks = matrix.iter().map(|&v| ((v + 0.5) * cells - 0.5).round() as usize).collect::<Vec<_>>();
+        ranks.sort();
+        ranks
+    }
+
+    #[test]
+    fn threshold_maps_are_permutations() {
+        for size in [2, 4, 8, 16] {
+            assert_eq!(ranks(&Dither::bayer_matrix(size)), (0..size * size).collect::<Vec<_>>(), "bayer {}", size);
+        }
+        assert_eq!(ranks(&Dither::blue_noise_matrix(16)), (0..256).collect::<Vec<_>>(), "blue noise");
+    }
+}
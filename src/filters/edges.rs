@@ -0,0 +1,194 @@
+use ndarray::{Array1, Array2, Array3, stack, Axis, Zip};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::filters::{blur::{Blur, BlurMode}, grayscale::Grayscale};
+use crate::linalg::{convolve_bordered, outer_product, hysteresis, BorderMode};
+
+pub struct Edges {
+    method: EdgeMethod,
+    border: BorderMode,
+}
+
+#[derive(Debug)]
+pub enum EdgeMethod {
+    Sobel,
+    Scharr,
+    Prewitt,
+    Laplacian { radius: i32 },
+    Canny { low: f64, high: f64, radius: i32 },
+}
+
+// the Laplacian response of a smoothed edge is much weaker than a gradient, this brings it to a visible range
+const LAPLACIAN_GAIN: f64 = 4.0;
+
+impl Edges {
+    pub fn new(method: EdgeMethod) -> Self {
+        let method = match method {
+            EdgeMethod::Laplacian { radius } => EdgeMethod::Laplacian { radius: radius.clamp(0, 50) },
+            EdgeMethod::Canny { low, high, radius } => EdgeMethod::Canny {
+                low: low.min(high).clamp(0.0, 255.0),
+                high: low.max(high).clamp(0.0, 255.0),
+                radius: radius.clamp(0, 50),
+            },
+            _ => method,
+        };
+        Self { method, border: BorderMode::Clamp }
+    }
+
+    pub fn with_border(mut self, border: BorderMode) -> Self {
+        self.border = border;
+        self
+    }
+
+    // constant borders are converted to luma like the image
+    fn convolve(&self, luma: &Array2<f64>, kernel: &Array2<f64>) -> Array2<f64> {
+        let color = Array3::from_shape_fn((1, 1, 3), |(_, _, c)| self.border.fill(c) as u8);
+        convolve_bordered(luma, kernel, &self.border, Grayscale::new().luma(&color)[[0, 0]])
+    }
+
+    // horizontal and vertical gradient kernels, scaled so that a full black to white step gives 255
+    // https://en.wikipedia.org/wiki/Sobel_operator#Alternative_operators
+    fn gradient_kernels(&self) -> (Array2<f64>, Array2<f64>) {
+        let smoothing = match self.method {
+            EdgeMethod::Scharr => vec![3.0, 10.0, 3.0],
+            EdgeMethod::Prewitt => vec![1.0, 1.0, 1.0],
+            _ => vec![1.0, 2.0, 1.0],
+        };
+        let smoothing = Array1::from(smoothing.clone()) / smoothing.iter().sum::<f64>();
+        let derivative = Array1::from(vec![-1.0, 0.0, 1.0]);
+        (outer_product(&derivative, &smoothing), outer_product(&smoothing, &derivative))
+    }
+
+    fn gradients(&self, luma: &Array2<f64>) -> (Array2<f64>, Array2<f64>) {
+        let (kernel_x, kernel_y) = self.gradient_kernels();
+        (self.convolve(luma, &kernel_x), self.convolve(luma, &kernel_y))
+    }
+
+    fn smooth(&self, luma: &Array2<f64>, radius: i32) -> Array2<f64> {
+        self.convolve(luma, &Blur::new(radius, BlurMode::Gaussian).kernel())
+    }
+
+    // https://en.wikipedia.org/wiki/Canny_edge_detector
+    fn canny(&self, luma: &Array2<f64>, low: f64, high: f64, radius: i32) -> Array2<f64> {
+        let (gx, gy) = self.gradients(&self.smooth(luma, radius));
+        let magnitude = Zip::from(&gx).and(&gy).map_collect(|&x, &y| x.hypot(y));
+        let (width, height) = magnitude.dim();
+
+        // non-maximum suppression, only pixels stronger than both neighbours across the edge are kept
+        let mut thin = Array2::<f64>::zeros((width, height));
+        for ((x, y), &m) in magnitude.indexed_iter() {
+            if m == 0.0 {
+                continue;
+            }
+            let angle = gy[[x, y]].atan2(gx[[x, y]]).to_degrees().rem_euclid(180.0);
+            let (dx, dy) = match angle {
+                a if !(22.5..157.5).contains(&a) => (1, 0),
+                a if a < 67.5 => (1, 1),
+                a if a < 112.5 => (0, 1),
+                _ => (-1, 1),
+            };
+            let neighbour = |sign: i32| {
+                let (x_, y_) = (x as i32 + dx * sign, y as i32 + dy * sign);
+                if x_ < 0 || y_ < 0 || x_ >= width as i32 || y_ >= height as i32 {
+                    0.0
+                } else {
+                    magnitude[[x_ as usize, y_ as usize]]
+                }
+            };
+            if m >= neighbour(1) && m >= neighbour(-1) {
+                thin[[x, y]] = m;
+            }
+        }
+
+        hysteresis(&thin, low, high).mapv(|x| if x { 255.0 } else { 0.0 })
+    }
+}
+
+impl Manipulate for Edges {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let luma = Grayscale::new().luma(img);
+
+        let edges = match self.method {
+            EdgeMethod::Sobel | EdgeMethod::Scharr | EdgeMethod::Prewitt => {
+                let (gx, gy) = self.gradients(&luma);
+                Zip::from(&gx).and(&gy).map_collect(|&x, &y| x.hypot(y))
+            },
+            // https://en.wikipedia.org/wiki/Blob_detection#The_Laplacian_of_Gaussian
+            EdgeMethod::Laplacian { radius } => {
+                let laplacian = Array2::from_shape_vec((3, 3), vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0]).unwrap();
+                self.convolve(&self.smooth(&luma, radius), &laplacian).mapv(|x| x.abs() * LAPLACIAN_GAIN)
+            },
+            EdgeMethod::Canny { low, high, radius } => self.canny(&luma, low, high, radius),
+        };
+
+        let res_chan = edges.mapv(|x| x.clamp(0.0, 255.0).round() as u8);
+        stack(Axis(2), &[res_chan.view(), res_chan.view(), res_chan.view()]).unwrap()
+    }
+
+    fn details_str(&self) -> String {
+        format!("Edges -> method: {:?}, border: {:?}", self.method, self.border)
+    }
+
+    // wrapped borders read the opposite edge of the image
+    fn neighborhood(&self) -> Option<usize> {
+        match self.method {
+            _ if self.border == BorderMode::Wrap => None,
+            EdgeMethod::Sobel | EdgeMethod::Scharr | EdgeMethod::Prewitt => Some(1),
+            EdgeMethod::Laplacian { radius } => Some(radius as usize + 1),
+            // hysteresis follows edges across the whole image
+            EdgeMethod::Canny { .. } => None,
+        }
+    }
+
+    // the gradient operators are fixed 3x3 kernels, only the smoothing radius depends on the image size
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        let scale = |radius: i32| (radius as f64 * factor).round() as i32;
+        let method = match self.method {
+            EdgeMethod::Laplacian { radius } => EdgeMethod::Laplacian { radius: scale(radius) },
+            EdgeMethod::Canny { low, high, radius } => EdgeMethod::Canny { low, high, radius: scale(radius) },
+            _ => return None,
+        };
+        Some(Filter::Edges(Edges::new(method).with_border(self.border)))
+    }
+}
+
+const METHODS: &str = "sobel, scharr, prewitt, laplacian or canny";
+const GRADIENT: Param = Param::required("operator", ParamKind::Choice(&["sobel", "scharr", "prewitt"]), "sobel");
+const LOG_RADIUS: Param = Param::optional("radius", ParamKind::Int(0, 50), Some("2"), "3");
+const LOW: Param = Param::optional("low", ParamKind::Number(0.0, 255.0), Some("10"), "20");
+const HIGH: Param = Param::optional("high", ParamKind::Number(0.0, 255.0), Some("30"), "60");
+const BORDER: Param = Param::optional("border", ParamKind::Border, Some("clamp"), "reflect");
+
+impl CommandParse for Edges {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+
+        // the border follows the parameters of the method
+        let (method, border) = match arg(0) {
+            "sobel" => (EdgeMethod::Sobel, 1),
+            "scharr" => (EdgeMethod::Scharr, 1),
+            "prewitt" => (EdgeMethod::Prewitt, 1),
+            "laplacian" => (EdgeMethod::Laplacian { radius: LOG_RADIUS.parse::<i32>("edges", arg(1))? }, 2),
+            "canny" => (EdgeMethod::Canny {
+                low: LOW.parse::<f64>("edges", arg(1))?,
+                high: HIGH.parse::<f64>("edges", arg(2))?,
+                radius: LOG_RADIUS.parse::<i32>("edges", arg(3))?,
+            }, 4),
+            "" => return Err(MagrustteError::missing_parameter("edges", "method", METHODS)),
+            method => return Err(MagrustteError::bad_parameter("edges", "method", method, METHODS)),
+        };
+        let border = BORDER.parse::<BorderMode>("edges", arg(border))?;
+        Ok(Filter::Edges(Edges::new(method).with_border(border)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "edges",
+            forms: &[
+                Form { keywords: "", params: &[GRADIENT, BORDER], description: "gradient magnitude edge map" },
+                Form { keywords: "laplacian", params: &[LOG_RADIUS, BORDER], description: "Laplacian of Gaussian edge map" },
+                Form { keywords: "canny", params: &[LOW, HIGH, LOG_RADIUS, BORDER], description: "thin edges with hysteresis thresholds" },
+            ],
+        }
+    }
+}
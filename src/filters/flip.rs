@@ -0,0 +1,57 @@
+use ndarray::{Array3, s};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+
+pub struct Flip {
+    direction: FlipDirection,
+}
+
+#[derive(Debug)]
+pub enum FlipDirection {
+    Horizontal,
+    Vertical,
+}
+
+impl std::str::FromStr for FlipDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "h" | "horizontal" => Ok(FlipDirection::Horizontal),
+            "v" | "vertical" => Ok(FlipDirection::Vertical),
+            _ => Err(format!("{} is not a valid flip direction", s)),
+        }
+    }
+}
+
+impl Flip {
+    pub fn new(direction: FlipDirection) -> Self {
+        Self { direction }
+    }
+}
+
+impl Manipulate for Flip {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        match self.direction {
+            FlipDirection::Horizontal => img.slice(s![..;-1, .., ..]).to_owned(),
+            FlipDirection::Vertical => img.slice(s![.., ..;-1, ..]).to_owned(),
+        }
+    }
+
+    fn details_str(&self) -> String {
+        format!("Flip -> direction: {:?}", self.direction)
+    }
+}
+
+const DIRECTION: Param = Param::required("direction", ParamKind::Choice(&["h", "v"]), "h");
+
+impl CommandParse for Flip {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let direction = DIRECTION.parse::<FlipDirection>("flip", command.first().map_or("", |s| s.as_str()))?;
+        Ok(Filter::Flip(Flip::new(direction)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "flip", forms: &[Form { keywords: "", params: &[DIRECTION], description: "" }] }
+    }
+}
@@ -1,23 +1,161 @@
-use ndarray::{Array2, Array3, stack, Axis};
-use crate::filters::Manipulate;
+use ndarray::{Array2, Array3, Zip};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
 use crate::imgarray::AsImage;
+use crate::color::{Rgb, parse_hex, to_hex, rgb_to_lab};
+use crate::linalg::par_image_from_fn;
 
-#[derive(Default)]
-pub struct Grayscale;
+pub struct Grayscale {
+    mode: GrayscaleMode,
+    tint: Option<(Rgb, Rgb)>,
+}
+
+#[derive(Debug)]
+pub enum GrayscaleMode {
+    Rec601,
+    Rec709,
+    Average,
+    Lightness,
+    Max,
+    Min,
+    Lab,
+    Custom(f64, f64, f64),
+}
+
+impl std::str::FromStr for GrayscaleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rec601" => Ok(GrayscaleMode::Rec601),
+            "rec709" => Ok(GrayscaleMode::Rec709),
+            "average" => Ok(GrayscaleMode::Average),
+            "lightness" => Ok(GrayscaleMode::Lightness),
+            "max" => Ok(GrayscaleMode::Max),
+            "min" => Ok(GrayscaleMode::Min),
+            "lab" => Ok(GrayscaleMode::Lab),
+            _ => Err(format!("{} is not a valid grayscale mode", s)),
+        }
+    }
+}
+
+impl Default for Grayscale {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Grayscale {
-    pub fn new() -> Self { Self }
+    pub fn new() -> Self {
+        Self { mode: GrayscaleMode::Rec709, tint: None }
+    }
+
+    pub fn with_mode(mode: GrayscaleMode, tint: Option<(Rgb, Rgb)>) -> Self {
+        Self { mode, tint }
+    }
+
+    pub fn luma(&self, img: &Array3<u8>) -> Array2<f64> {
+        let (r, g, b) = img.rgb_as_float();
+        match self.mode {
+            // https://en.wikipedia.org/wiki/Grayscale#Luma_coding_in_video_systems
+            GrayscaleMode::Rec601 => r * 0.299 + g * 0.587 + b * 0.114,
+            // https://en.wikipedia.org/wiki/Grayscale#Colorimetric_(perceptual_luminance-preserving)_conversion_to_grayscale
+            GrayscaleMode::Rec709 => r * 0.2126 + g * 0.7152 + b * 0.0722,
+            GrayscaleMode::Average => (r + g + b) / 3.0,
+            GrayscaleMode::Custom(wr, wg, wb) => r * wr + g * wg + b * wb,
+            GrayscaleMode::Lightness => Zip::from(&r).and(&g).and(&b)
+                .map_collect(|&r, &g, &b| (r.max(g).max(b) + r.min(g).min(b)) / 2.0),
+            GrayscaleMode::Max => Zip::from(&r).and(&g).and(&b)
+                .map_collect(|&r, &g, &b| r.max(g).max(b)),
+            GrayscaleMode::Min => Zip::from(&r).and(&g).and(&b)
+                .map_collect(|&r, &g, &b| r.min(g).min(b)),
+            // L* spans 0..100, scaled to the 0..255 range of the other modes
+            GrayscaleMode::Lab => Zip::from(&r).and(&g).and(&b)
+                .map_collect(|&r, &g, &b| rgb_to_lab(r, g, b)[0] * 2.55),
+        }
+    }
 }
 
 impl Manipulate for Grayscale {
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
-        let (r, g, b) = img.rgb_as_float();
-        let luma: Array2<f64> = r * 0.2126 + g * 0.7152 + b * 0.0722; // https://en.wikipedia.org/wiki/Grayscale#Colorimetric_(perceptual_luminance-preserving)_conversion_to_grayscale
-        let res_chan = luma.mapv(|x| x as u8);
-        stack(Axis(2), &[res_chan.view(), res_chan.view(), res_chan.view()]).unwrap()
+        let luma = self.luma(img).mapv(|x| x.clamp(0.0, 255.0));
+        let (width, height) = luma.dim();
+
+        match self.tint {
+            None => par_image_from_fn(width, height, |x, y| [luma[[x, y]].round() as u8; 3]),
+            // duotone: shadows are mapped to the first color, highlights to the second one
+            Some((dark, light)) => par_image_from_fn(width, height, |x, y| {
+                let t = luma[[x, y]] / 255.0;
+                let tint = |c: usize| (dark[c] as f64 * (1.0 - t) + light[c] as f64 * t).round() as u8;
+                [tint(0), tint(1), tint(2)]
+            }),
+        }
     }
 
     fn details_str(&self) -> String {
-        "Grayscale".to_string()
+        match self.tint {
+            None => format!("Grayscale -> mode: {:?}", self.mode),
+            Some((dark, light)) => format!("Grayscale -> mode: {:?}, tint: {} to {}",
+                self.mode, to_hex(&dark), to_hex(&light)),
+        }
+    }
+
+    fn neighborhood(&self) -> Option<usize> {
+        Some(0)
     }
-}
\ No newline at end of file
+}
+
+const MODE: Param = Param::optional(
+    "mode",
+    ParamKind::Choice(&["rec601", "rec709", "average", "lightness", "max", "min", "lab"]),
+    Some("rec709"),
+    "lab",
+);
+const WEIGHTS: Param = Param::required("weights", ParamKind::Text("3 comma separated numbers r,g,b"), "0.3,0.6,0.1");
+const TINT_COLOR: Param = Param::required("color", ParamKind::Color, "#202040");
+const TINT_LIGHT: Param = Param::optional("light color", ParamKind::Color, None, "#ffeecc");
+const TINT: Param = Param::keyword("tint", &[TINT_COLOR, TINT_LIGHT]);
+
+impl CommandParse for Grayscale {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        // every argument is optional, plain 'grayscale' keeps the Rec.709 conversion
+        let mut args = command.iter().map(|s| s.as_str()).filter(|s| !s.is_empty()).peekable();
+
+        let mode = match args.peek() {
+            None | Some(&"tint") => GrayscaleMode::Rec709,
+            Some(&"custom") => {
+                args.next();
+                let weights = WEIGHTS.parse_list::<f64>("grayscale", args.next().unwrap_or(""), &[3])?;
+                GrayscaleMode::Custom(weights[0], weights[1], weights[2])
+            },
+            Some(_) => MODE.parse::<GrayscaleMode>("grayscale", args.next().unwrap_or(""))?,
+        };
+
+        let tint = match args.next() {
+            None => None,
+            Some("tint") => {
+                let first = TINT_COLOR.parse_with("grayscale", args.next().unwrap_or(""), parse_hex)?;
+                match args.next() {
+                    Some(second) => Some((first, TINT_LIGHT.parse_with("grayscale", second, parse_hex)?)),
+                    None => Some(([0, 0, 0], first)),
+                }
+            },
+            Some(s) => return Err(MagrustteError::unexpected_argument("grayscale", s)),
+        };
+
+        if let Some(s) = args.next() {
+            return Err(MagrustteError::unexpected_argument("grayscale", s));
+        }
+        Ok(Filter::Grayscale(Grayscale::with_mode(mode, tint)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "grayscale",
+            forms: &[
+                Form { keywords: "", params: &[MODE, TINT], description: "tint maps black and white to the two colors, or white to a single one" },
+                Form { keywords: "custom", params: &[WEIGHTS, TINT], description: "" },
+            ],
+        }
+    }
+}
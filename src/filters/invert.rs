@@ -1,5 +1,6 @@
 use ndarray::Array3;
-use crate::filters::Manipulate;
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Form, Manipulate, Usage};
 
 #[derive(Default)]
 pub struct Invert;
@@ -16,4 +17,21 @@ impl Manipulate for Invert {
     fn details_str(&self) -> String {
         "Color invert".to_string()
     }
+
+    fn neighborhood(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+impl CommandParse for Invert {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        match command.iter().find(|s| !s.is_empty()) {
+            Some(s) => Err(MagrustteError::unexpected_argument("invert", s)),
+            None => Ok(Filter::Invert(Invert::new())),
+        }
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "invert", forms: &[Form { keywords: "", params: &[], description: "negative of the image" }] }
+    }
 }
\ No newline at end of file
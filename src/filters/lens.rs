@@ -0,0 +1,93 @@
+use ndarray::Array3;
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::linalg::{BorderMode, Interpolation, sample_channel, par_image_from_fn};
+
+pub struct Lens {
+    k1: f64,
+    k2: f64,
+    // lateral chromatic aberration, red and blue are magnified relative to green
+    red_scale: f64,
+    blue_scale: f64,
+}
+
+impl Lens {
+    pub fn new(k1: f64, k2: f64, red_scale: f64, blue_scale: f64) -> Self {
+        Self {
+            k1: k1.clamp(-1.0, 1.0),
+            k2: k2.clamp(-1.0, 1.0),
+            red_scale: red_scale.clamp(0.9, 1.1),
+            blue_scale: blue_scale.clamp(0.9, 1.1),
+        }
+    }
+}
+
+// radial distortion after the Brown-Conrady model https://en.wikipedia.org/wiki/Distortion_(optics)#Software_correction
+// every output pixel is sampled from r * (1 + k1 * r^2 + k2 * r^4), with r normalized to the half diagonal,
+// so negative k1 straightens barrel distortion and positive k1 straightens pincushion distortion
+impl Manipulate for Lens {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let (width, height, _) = img.dim();
+        let (cx, cy) = ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0);
+        let norm = (cx * cx + cy * cy).sqrt().max(1.0);
+        let scales = [self.red_scale, 1.0, self.blue_scale];
+        let border = BorderMode::Constant([0, 0, 0]);
+
+        par_image_from_fn(width, height, |x, y| {
+            let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+            let r2 = (dx * dx + dy * dy) / (norm * norm);
+            let factor = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+            let mut pixel = [0u8; 3];
+            for (c, scale) in scales.iter().enumerate() {
+                let (src_x, src_y) = (cx + dx * factor / scale, cy + dy * factor / scale);
+                let val = sample_channel(img, src_x, src_y, c, Interpolation::Bicubic, &border);
+                pixel[c] = val.clamp(0.0, 255.0).round() as u8;
+            }
+            pixel
+        })
+    }
+
+    fn details_str(&self) -> String {
+        format!("Lens -> k1: {}, k2: {}, red scale: {}, blue scale: {}",
+            self.k1, self.k2, self.red_scale, self.blue_scale)
+    }
+}
+
+const K1: Param = Param::required("k1", ParamKind::Number(-1.0, 1.0), "-0.1");
+const K2: Param = Param::optional("k2", ParamKind::Number(-1.0, 1.0), Some("0"), "0.02");
+const RED_SCALE: Param = Param::required("red scale", ParamKind::Number(0.9, 1.1), "1.002");
+const BLUE_SCALE: Param = Param::required("blue scale", ParamKind::Number(0.9, 1.1), "0.998");
+const CA: Param = Param::keyword("ca", &[RED_SCALE, BLUE_SCALE]);
+
+impl CommandParse for Lens {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let k1 = K1.parse::<f64>("lens", arg(0))?;
+        // k2 can be left out before ca as well
+        let (k2, ca) = match arg(1) {
+            "ca" => (K2.parse::<f64>("lens", "")?, 1),
+            k2 => (K2.parse::<f64>("lens", k2)?, 2),
+        };
+
+        let (red_scale, blue_scale, end) = match arg(ca) {
+            "" => (1.0, 1.0, ca + 1),
+            "ca" => (RED_SCALE.parse::<f64>("lens", arg(ca + 1))?, BLUE_SCALE.parse::<f64>("lens", arg(ca + 2))?, ca + 3),
+            s => return Err(MagrustteError::unexpected_argument("lens", s)),
+        };
+        if let Some(s) = command.iter().skip(end).find(|s| !s.is_empty()) {
+            return Err(MagrustteError::unexpected_argument("lens", s));
+        }
+        Ok(Filter::Lens(Lens::new(k1, k2, red_scale, blue_scale)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "lens",
+            forms: &[Form {
+                keywords: "",
+                params: &[K1, K2, CA],
+                description: "negative k1 corrects barrel, positive k1 pincushion distortion, ca scales the red and blue channels",
+            }],
+        }
+    }
+}
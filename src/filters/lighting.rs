@@ -1,5 +1,6 @@
 use ndarray::Array3;
-use crate::filters::{CommandParse, Filter, Manipulate};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
 
 pub struct Lighting {
     brightness: f64,
@@ -9,8 +10,8 @@ pub struct Lighting {
 impl Lighting {
     pub fn new(brightness: i32, contrast: i32) -> Self {
         Self {
-            brightness: brightness.max(-255).min(255) as f64,
-            contrast: contrast.max(-255).min(255) as f64,
+            brightness: brightness.clamp(-255, 255) as f64,
+            contrast: contrast.clamp(-255, 255) as f64,
         }
     }
 }
@@ -23,27 +24,31 @@ impl Manipulate for Lighting {
 
         img.mapv(|x| {
             let c = f * (x as f64 - 128.0) + 128.0 + self.brightness;
-            c.min(255.0).max(0.0).round() as u8
+            c.clamp(0.0, 255.0).round() as u8
         })
     }
 
     fn details_str(&self) -> String {
         format!("Lighting -> brightness: {}, contrast: {}", self.brightness, self.contrast)
     }
+
+    fn neighborhood(&self) -> Option<usize> {
+        Some(0)
+    }
 }
 
+const BRIGHTNESS: Param = Param::required("brightness", ParamKind::Int(-255, 255), "20");
+const CONTRAST: Param = Param::required("contrast", ParamKind::Int(-255, 255), "10");
+
 impl CommandParse for Lighting {
-    fn parse(command: Vec<String>) -> Result<Filter, Box<dyn std::error::Error>> {
-        let maybe_brightness = match command.get(0) {
-            Some(s) => s,
-            None => "nan",
-        };
-        let maybe_contrast = match command.get(1) {
-            Some(s) => s,
-            None => "nan",
-        };
-        let brightness = maybe_brightness.parse::<i32>()?;
-        let contrast = maybe_contrast.parse::<i32>()?;
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let brightness = BRIGHTNESS.parse::<i32>("lighting", arg(0))?;
+        let contrast = CONTRAST.parse::<i32>("lighting", arg(1))?;
         Ok(Filter::Lighting(Lighting::new(brightness, contrast)))
     }
+
+    fn usage() -> Usage {
+        Usage { name: "lighting", forms: &[Form { keywords: "", params: &[BRIGHTNESS, CONTRAST], description: "" }] }
+    }
 }
\ No newline at end of file
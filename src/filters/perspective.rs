@@ -0,0 +1,150 @@
+use ndarray::{Array1, Array2, Array3};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::linalg::{BorderMode, Interpolation, sample, solve, par_image_from_fn};
+
+pub struct Perspective {
+    // top-left, top-right, bottom-right and bottom-left corners of the quadrilateral
+    corners: [(f64, f64); 4],
+    interpolation: Interpolation,
+    border: BorderMode,
+}
+
+/// Projective transform mapping each `from` point onto the matching `to` point, as a row-major 3x3 matrix.
+/// <https://docs.opencv.org/4.x/da/d54/group__imgproc__transform.html#ga20f62aa3235d869c9956436c870893ae>
+pub fn homography(from: &[(f64, f64); 4], to: &[(f64, f64); 4]) -> Option<[f64; 9]> {
+    let mut a = Array2::<f64>::zeros((8, 8));
+    let mut b = Array1::<f64>::zeros(8);
+    for (i, (&(u, v), &(x, y))) in from.iter().zip(to.iter()).enumerate() {
+        let (r1, r2) = (2 * i, 2 * i + 1);
+        for (k, val) in [u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x].iter().enumerate() {
+            a[[r1, k]] = *val;
+        }
+        for (k, val) in [0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y].iter().enumerate() {
+            a[[r2, k]] = *val;
+        }
+        b[r1] = x;
+        b[r2] = y;
+    }
+    let h = solve(&a, &b)?;
+    Some([h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], 1.0])
+}
+
+impl Perspective {
+    pub fn new(corners: [(f64, f64); 4], interpolation: Interpolation, border: BorderMode) -> Self {
+        Self { corners, interpolation, border }
+    }
+}
+
+impl Manipulate for Perspective {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let distance = |a: (f64, f64), b: (f64, f64)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+        let [tl, tr, br, bl] = self.corners;
+        // the rectangle keeps the longer of each pair of opposite edges, so no detail is lost
+        let width = distance(tl, tr).max(distance(bl, br)).round().max(1.0) as usize;
+        let height = distance(tl, bl).max(distance(tr, br)).round().max(1.0) as usize;
+
+        let (w, h) = ((width - 1) as f64, (height - 1) as f64);
+        let rect = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)];
+        let m = match homography(&rect, &self.corners) {
+            Some(m) => m,
+            None => return img.clone(), // degenerate quadrilateral, e.g. three collinear corners
+        };
+
+        par_image_from_fn(width, height, |x, y| {
+            let (u, v) = (x as f64, y as f64);
+            let z = m[6] * u + m[7] * v + m[8];
+            let src_x = (m[0] * u + m[1] * v + m[2]) / z;
+            let src_y = (m[3] * u + m[4] * v + m[5]) / z;
+            sample(img, src_x, src_y, self.interpolation, &self.border).map(|v| v.clamp(0.0, 255.0).round() as u8)
+        })
+    }
+
+    fn details_str(&self) -> String {
+        let corners = self.corners
+            .iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect::<Vec<String>>()
+            .join(" ");
+        format!("Perspective -> corners: {}, interpolation: {:?}, border: {:?}",
+            corners, self.interpolation, self.border)
+    }
+
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        let corners = self.corners.map(|(x, y)| (x * factor, y * factor));
+        Some(Filter::Perspective(Perspective::new(corners, self.interpolation, self.border)))
+    }
+}
+
+/// Optional trailing interpolation and border arguments shared by the warping filters, in any order.
+pub const INTERPOLATION: Param = Param::optional(
+    "interpolation",
+    ParamKind::Choice(&["nearest", "bilinear", "bicubic"]),
+    Some("bilinear"),
+    "bicubic",
+);
+pub const SAMPLING_BORDER: Param = Param::optional("border", ParamKind::Border, Some("constant"), "clamp");
+
+pub fn parse_sampling(args: &[String], filter: &str) -> Result<(Interpolation, BorderMode), MagrustteError> {
+    let (mut interpolation, mut border) = (Interpolation::Bilinear, BorderMode::Constant([0, 0, 0]));
+    for arg in args.iter().filter(|s| !s.is_empty()) {
+        match (arg.parse::<Interpolation>(), arg.parse::<BorderMode>()) {
+            (Ok(i), _) => interpolation = i,
+            (_, Ok(b)) => border = b,
+            _ => return Err(MagrustteError::bad_parameter(
+                filter,
+                "interpolation or border",
+                arg,
+                &format!("{}, {}", INTERPOLATION.expected(), SAMPLING_BORDER.expected()),
+            )),
+        }
+    }
+    Ok((interpolation, border))
+}
+
+const TOP_LEFT: Param = Param::required("top-left", ParamKind::Text("a point like x,y"), "12,8");
+const TOP_RIGHT: Param = Param::required("top-right", ParamKind::Text("a point like x,y"), "1010,30");
+const BOTTOM_RIGHT: Param = Param::required("bottom-right", ParamKind::Text("a point like x,y"), "1000,760");
+const BOTTOM_LEFT: Param = Param::required("bottom-left", ParamKind::Text("a point like x,y"), "5,740");
+
+impl CommandParse for Perspective {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let mut corners = [(0.0, 0.0); 4];
+        for (i, (corner, param)) in corners.iter_mut().zip([TOP_LEFT, TOP_RIGHT, BOTTOM_RIGHT, BOTTOM_LEFT]).enumerate() {
+            let point = param.parse_list::<f64>("perspective", command.get(i).map_or("", |s| s.as_str()), &[2])?;
+            *corner = (point[0], point[1]);
+        }
+        let (interpolation, border) = parse_sampling(command.get(4..).unwrap_or(&[]), "perspective")?;
+        Ok(Filter::Perspective(Perspective::new(corners, interpolation, border)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "perspective",
+            forms: &[Form {
+                keywords: "",
+                params: &[TOP_LEFT, TOP_RIGHT, BOTTOM_RIGHT, BOTTOM_LEFT, INTERPOLATION, SAMPLING_BORDER],
+                description: "straightens the quadrilateral with these corners into a rectangle",
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn homography_maps_corners() {
+        let rect = [(0.0, 0.0), (99.0, 0.0), (99.0, 59.0), (0.0, 59.0)];
+        let quad = [(12.0, 8.0), (110.5, 30.0), (100.0, 76.0), (5.0, 74.0)];
+        let m = homography(&rect, &quad).unwrap();
+        for (&(u, v), &(x, y)) in rect.iter().zip(quad.iter()) {
+            let z = m[6] * u + m[7] * v + m[8];
+            let (res_x, res_y) = ((m[0] * u + m[1] * v + m[2]) / z, (m[3] * u + m[4] * v + m[5]) / z);
+            assert!((res_x - x).abs() < 1e-9 && (res_y - y).abs() < 1e-9, "{},{} maps to {},{}", u, v, res_x, res_y);
+        }
+        // a rectangle collapsed to a line can't be mapped onto a quadrilateral
+        assert!(homography(&[(0.0, 0.0), (0.0, 0.0), (0.0, 59.0), (0.0, 59.0)], &quad).is_none());
+    }
+}
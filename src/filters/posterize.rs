@@ -0,0 +1,41 @@
+use ndarray::Array3;
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+
+pub struct Posterize {
+    levels: u32,
+}
+
+impl Posterize {
+    pub fn new(levels: i32) -> Self {
+        Self { levels: levels.clamp(2, 256) as u32 }
+    }
+}
+
+impl Manipulate for Posterize {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let step = 255.0 / (self.levels - 1) as f64;
+        img.mapv(|x| ((x as f64 / step).round() * step).round() as u8)
+    }
+
+    fn details_str(&self) -> String {
+        format!("Posterize -> levels: {}", self.levels)
+    }
+
+    fn neighborhood(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+const LEVELS: Param = Param::required("levels", ParamKind::Int(2, 256), "4");
+
+impl CommandParse for Posterize {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let levels = LEVELS.parse::<i32>("posterize", command.first().map_or("", |s| s.as_str()))?;
+        Ok(Filter::Posterize(Posterize::new(levels)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "posterize", forms: &[Form { keywords: "", params: &[LEVELS], description: "levels per channel" }] }
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use ndarray::{Array3, Axis};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::color::{Rgb, Palette, rgb_to_lab};
+use crate::linalg::par_image_from_fn;
+use crate::progress::Reporter;
+
+pub struct Quantize {
+    colors: usize,
+    method: QuantizeMethod,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum QuantizeMethod {
+    MedianCut,
+    KMeans,
+}
+
+impl std::str::FromStr for QuantizeMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mediancut" => Ok(QuantizeMethod::MedianCut),
+            "kmeans" => Ok(QuantizeMethod::KMeans),
+            _ => Err(format!("{} is not a valid quantization method", s)),
+        }
+    }
+}
+
+const KMEANS_ITERATIONS: usize = 16;
+
+/// Unique colors with their pixel counts, so that the algorithms below don't have to visit every pixel.
+pub fn color_histogram(img: &Array3<u8>) -> Vec<(Rgb, usize)> {
+    let mut histogram = HashMap::<Rgb, usize>::new();
+    for pixel in img.lanes(Axis(2)) {
+        *histogram.entry([pixel[0], pixel[1], pixel[2]]).or_insert(0) += 1;
+    }
+    let mut res = histogram.into_iter().collect::<Vec<(Rgb, usize)>>();
+    // HashMap iteration order is random, sorting keeps the results reproducible
+    res.sort();
+    res
+}
+
+fn weighted_mean(colors: &[(Rgb, usize)]) -> Rgb {
+    let total = colors.iter().map(|(_, n)| *n).sum::<usize>().max(1) as f64;
+    let mean = |c: usize| (colors.iter().map(|(color, n)| color[c] as f64 * *n as f64).sum::<f64>() / total).round() as u8;
+    [mean(0), mean(1), mean(2)]
+}
+
+/// <https://en.wikipedia.org/wiki/Median_cut>
+pub fn median_cut(histogram: &[(Rgb, usize)], n: usize) -> Vec<Rgb> {
+    let mut boxes = vec![histogram.to_vec()];
+
+    while boxes.len() < n {
+        // split the box with the widest channel range, weighted by how many pixels it holds
+        let widest = boxes.iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let (channel, range) = (0..3)
+                    .map(|c| {
+                        let min = b.iter().map(|(color, _)| color[c]).min().unwrap();
+                        let max = b.iter().map(|(color, _)| color[c]).max().unwrap();
+                        (c, (max - min) as usize)
+                    })
+                    .max_by_key(|(_, range)| *range)
+                    .unwrap();
+                let population = b.iter().map(|(_, n)| *n).sum::<usize>();
+                (i, channel, range * population)
+            })
+            .max_by_key(|(_, _, score)| *score);
+
+        let (index, channel) = match widest {
+            Some((index, channel, _)) => (index, channel),
+            None => break, // fewer unique colors than requested
+        };
+
+        let mut current = boxes.swap_remove(index);
+        current.sort_by_key(|(color, _)| color[channel]);
+        let half = current.iter().map(|(_, n)| *n).sum::<usize>() / 2;
+        let mut acc = 0;
+        let split = current.iter()
+            .position(|(_, n)| { acc += n; acc >= half })
+            .unwrap_or(0)
+            .clamp(0, current.len() - 2) + 1;
+        let upper = current.split_off(split);
+        boxes.push(current);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|b| weighted_mean(b)).collect()
+}
+
+fn lab_distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn nearest_centroid(lab: &[f64; 3], centroids: &[[f64; 3]]) -> usize {
+    let mut best = (0, f64::MAX);
+    for (i, centroid) in centroids.iter().enumerate() {
+        let distance = lab_distance(lab, centroid);
+        if distance < best.1 {
+            best = (i, distance);
+        }
+    }
+    best.0
+}
+
+/// K-means in CIELAB, so that the clusters follow perceived color differences.
+/// Seeded with the median cut palette, which keeps the result deterministic.
+pub fn kmeans(histogram: &[(Rgb, usize)], n: usize) -> Vec<Rgb> {
+    let labs = histogram.iter()
+        .map(|(c, _)| rgb_to_lab(c[0] as f64, c[1] as f64, c[2] as f64))
+        .collect::<Vec<[f64; 3]>>();
+    let mut centroids = median_cut(histogram, n).iter()
+        .map(|c| rgb_to_lab(c[0] as f64, c[1] as f64, c[2] as f64))
+        .collect::<Vec<[f64; 3]>>();
+    let mut assignments = vec![0; histogram.len()];
+
+    let reporter = Reporter::current();
+    reporter.start_pass(KMEANS_ITERATIONS);
+    for _ in 0..KMEANS_ITERATIONS {
+        if reporter.is_cancelled() {
+            break;
+        }
+        let mut changed = false;
+        for (i, lab) in labs.iter().enumerate() {
+            let nearest = nearest_centroid(lab, &centroids);
+            changed |= nearest != assignments[i];
+            assignments[i] = nearest;
+        }
+
+        let mut sums = vec![([0.0; 3], 0.0); centroids.len()];
+        for (i, lab) in labs.iter().enumerate() {
+            let (sum, weight) = &mut sums[assignments[i]];
+            let n = histogram[i].1 as f64;
+            (0..3).for_each(|c| sum[c] += lab[c] * n);
+            *weight += n;
+        }
+        for (centroid, (sum, weight)) in centroids.iter_mut().zip(sums) {
+            if weight > 0.0 {
+                *centroid = [sum[0] / weight, sum[1] / weight, sum[2] / weight];
+            }
+        }
+
+        reporter.line_done();
+        if !changed {
+            break;
+        }
+    }
+
+    // centroids are averaged in Lab, so the final colors are the RGB means of their clusters
+    (0..centroids.len())
+        .map(|k| histogram.iter()
+            .zip(&assignments)
+            .filter(|(_, &a)| a == k)
+            .map(|(h, _)| *h)
+            .collect::<Vec<(Rgb, usize)>>())
+        .filter(|cluster| !cluster.is_empty())
+        .map(|cluster| weighted_mean(&cluster))
+        .collect()
+}
+
+impl Quantize {
+    pub fn new(colors: i32, method: QuantizeMethod) -> Self {
+        Self { colors: colors.clamp(2, 256) as usize, method }
+    }
+
+    pub fn palette(&self, histogram: &[(Rgb, usize)]) -> Palette {
+        let colors = match self.method {
+            QuantizeMethod::MedianCut => median_cut(histogram, self.colors),
+            QuantizeMethod::KMeans => kmeans(histogram, self.colors),
+        };
+        Palette::new(&format!("{:?}", self.method), colors)
+    }
+
+    /// Palette colors paired with the share of pixels mapped to them, most frequent first.
+    pub fn dominant_colors(&self, img: &Array3<u8>) -> Vec<(Rgb, f64)> {
+        let histogram = color_histogram(img);
+        let palette = self.palette(&histogram);
+        let mut counts = vec![0usize; palette.colors.len()];
+        for (color, n) in &histogram {
+            let nearest = palette.nearest(&[color[0] as f64, color[1] as f64, color[2] as f64]);
+            counts[palette.colors.iter().position(|c| *c == nearest).unwrap()] += n;
+        }
+
+        let total = img.len() as f64 / 3.0;
+        let mut res = palette.colors.into_iter()
+            .zip(counts)
+            .map(|(color, n)| (color, n as f64 / total))
+            .collect::<Vec<(Rgb, f64)>>();
+        res.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        res
+    }
+}
+
+impl Manipulate for Quantize {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let histogram = color_histogram(img);
+        let palette = self.palette(&histogram);
+        let mapping = histogram.iter()
+            .map(|(c, _)| (*c, palette.nearest(&[c[0] as f64, c[1] as f64, c[2] as f64])))
+            .collect::<HashMap<Rgb, Rgb>>();
+
+        let (width, height, _) = img.dim();
+        par_image_from_fn(width, height, |x, y| mapping[&[img[[x, y, 0]], img[[x, y, 1]], img[[x, y, 2]]]])
+    }
+
+    fn details_str(&self) -> String {
+        format!("Quantize -> colors: {}, method: {:?}", self.colors, self.method)
+    }
+}
+
+/// Shared with the palette command, which takes the same parameters.
+pub const COLORS: Param = Param::required("number of colors", ParamKind::Int(2, 256), "16");
+pub const METHOD: Param = Param::optional("method", ParamKind::Choice(&["mediancut", "kmeans"]), Some("mediancut"), "kmeans");
+
+impl CommandParse for Quantize {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let colors = COLORS.parse::<i32>("quantize", arg(0))?;
+        let method = METHOD.parse::<QuantizeMethod>("quantize", arg(1))?;
+        Ok(Filter::Quantize(Quantize::new(colors, method)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "quantize", forms: &[Form { keywords: "", params: &[COLORS, METHOD], description: "" }] }
+    }
+}
@@ -0,0 +1,243 @@
+use ndarray::{Array3, Axis, stack, s};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::imgarray::AsImage;
+use crate::linalg::{ResampleKernel, resample};
+
+pub struct Resize {
+    target: ResizeTarget,
+    kernel: ResampleKernel,
+    mode: ResizeMode,
+}
+
+#[derive(Debug)]
+pub enum ResizeTarget {
+    Pixels(usize, usize),
+    Percent(f64),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ResizeMode {
+    Fit,
+    Fill,
+    Exact,
+}
+
+impl std::str::FromStr for ResizeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fit" => Ok(ResizeMode::Fit),
+            "fill" => Ok(ResizeMode::Fill),
+            "exact" => Ok(ResizeMode::Exact),
+            _ => Err(format!("{} is not a valid resize mode", s)),
+        }
+    }
+}
+
+impl Resize {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self::with_options(
+            ResizeTarget::Pixels(width.clamp(1, 65535) as usize, height.clamp(1, 65535) as usize),
+            ResampleKernel::Bilinear,
+            ResizeMode::Exact,
+        )
+    }
+
+    pub fn with_options(target: ResizeTarget, kernel: ResampleKernel, mode: ResizeMode) -> Self {
+        let target = match target {
+            ResizeTarget::Percent(percent) => ResizeTarget::Percent(percent.clamp(0.1, 10000.0)),
+            _ => target,
+        };
+        Self { target, kernel, mode }
+    }
+
+    // size the image is resampled to, followed by the size it is cropped to afterwards
+    fn target_dims(&self, width: usize, height: usize) -> ((usize, usize), (usize, usize)) {
+        let scaled = |scale_x: f64, scale_y: f64| (
+            ((width as f64 * scale_x).round() as usize).max(1),
+            ((height as f64 * scale_y).round() as usize).max(1),
+        );
+        match self.target {
+            ResizeTarget::Percent(percent) => {
+                let dims = scaled(percent / 100.0, percent / 100.0);
+                (dims, dims)
+            },
+            ResizeTarget::Pixels(target_width, target_height) => {
+                let scale_x = target_width as f64 / width as f64;
+                let scale_y = target_height as f64 / height as f64;
+                match self.mode {
+                    ResizeMode::Exact => ((target_width, target_height), (target_width, target_height)),
+                    ResizeMode::Fit => {
+                        let dims = scaled(scale_x.min(scale_y), scale_x.min(scale_y));
+                        (dims, dims)
+                    },
+                    ResizeMode::Fill => {
+                        let dims = scaled(scale_x.max(scale_y), scale_x.max(scale_y));
+                        (dims, (target_width.min(dims.0), target_height.min(dims.1)))
+                    },
+                }
+            },
+        }
+    }
+}
+
+impl Manipulate for Resize {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        let (width, height, _) = img.dim();
+        let ((res_width, res_height), (crop_width, crop_height)) = self.target_dims(width, height);
+        let (rc, gc, bc) = img.rgb_as_float();
+
+        let res = stack(Axis(2), &[
+            resample(&rc, res_width, res_height, self.kernel).mapv(|x| x.clamp(0.0, 255.0).round() as u8).view(),
+            resample(&gc, res_width, res_height, self.kernel).mapv(|x| x.clamp(0.0, 255.0).round() as u8).view(),
+            resample(&bc, res_width, res_height, self.kernel).mapv(|x| x.clamp(0.0, 255.0).round() as u8).view(),
+        ]).unwrap();
+
+        // fill mode covers the whole target area and crops the overflow around the center
+        let (x, y) = ((res_width - crop_width) / 2, (res_height - crop_height) / 2);
+        res.slice(s![x..x + crop_width, y..y + crop_height, ..]).to_owned()
+    }
+
+    fn details_str(&self) -> String {
+        match self.target {
+            ResizeTarget::Percent(percent) =>
+                format!("Resize -> scale: {}%, kernel: {:?}", percent, self.kernel),
+            ResizeTarget::Pixels(width, height) =>
+                format!("Resize -> width: {}, height: {}, kernel: {:?}, mode: {:?}", width, height, self.kernel, self.mode),
+        }
+    }
+
+    // percentages are already relative to the image size
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        match self.target {
+            ResizeTarget::Percent(_) => None,
+            ResizeTarget::Pixels(width, height) => {
+                let scale = |v: usize| ((v as f64 * factor).round() as usize).max(1);
+                let target = ResizeTarget::Pixels(scale(width), scale(height));
+                Some(Filter::Resize(Resize::with_options(target, self.kernel, self.mode)))
+            },
+        }
+    }
+}
+
+const WIDTH: Param = Param::required("width", ParamKind::Int(1, 65535), "800");
+const HEIGHT: Param = Param::required("height", ParamKind::Int(1, 65535), "600");
+const PERCENT: Param = Param::required("percent", ParamKind::Number(0.1, 10000.0), "50%");
+const KERNEL: Param = Param::optional(
+    "kernel",
+    ParamKind::Choice(&["nearest", "bilinear", "bicubic", "mitchell", "lanczos3"]),
+    Some("bilinear"),
+    "lanczos3",
+);
+const MODE: Param = Param::optional("mode", ParamKind::Choice(&["fit", "fill", "exact"]), Some("exact"), "fit");
+
+impl CommandParse for Resize {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let mut args = command.iter().map(|s| s.as_str()).filter(|s| !s.is_empty());
+
+        let maybe_size = args.next().unwrap_or("");
+        let target = match maybe_size.strip_suffix('%') {
+            Some(percent) => ResizeTarget::Percent(PERCENT.parse::<f64>("resize", percent)?),
+            None => {
+                let width = WIDTH.parse::<i32>("resize", maybe_size)?;
+                let height = HEIGHT.parse::<i32>("resize", args.next().unwrap_or(""))?;
+                ResizeTarget::Pixels(width as usize, height as usize)
+            },
+        };
+
+        let (mut kernel, mut mode) = (ResampleKernel::Bilinear, ResizeMode::Exact);
+        for arg in args {
+            match (arg.parse::<ResampleKernel>(), arg.parse::<ResizeMode>()) {
+                (Ok(k), _) => kernel = k,
+                (_, Ok(m)) => mode = m,
+                _ => return Err(MagrustteError::bad_parameter(
+                    "resize",
+                    "kernel or mode",
+                    arg,
+                    &format!("a kernel ({}) or a mode ({})", KERNEL.expected(), MODE.expected()),
+                )),
+            }
+        }
+        Ok(Filter::Resize(Resize::with_options(target, kernel, mode)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "resize",
+            forms: &[
+                Form { keywords: "", params: &[WIDTH, HEIGHT, KERNEL, MODE], description: "" },
+                Form { keywords: "", params: &[PERCENT, KERNEL], description: "the percentage is followed by %" },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgarray::noise_image;
+
+    const KERNELS: [ResampleKernel; 5] = [
+        ResampleKernel::Nearest,
+        ResampleKernel::Bilinear,
+        ResampleKernel::CatmullRom,
+        ResampleKernel::Mitchell,
+        ResampleKernel::Lanczos3,
+    ];
+
+    fn resize(img: &Array3<u8>, target: ResizeTarget, kernel: ResampleKernel, mode: ResizeMode) -> Array3<u8> {
+        Resize::with_options(target, kernel, mode).apply(img)
+    }
+
+    #[test]
+    fn modes_give_target_dimensions() {
+        let img = noise_image(40, 20, 33);
+        for (target, mode, dims) in [
+            (ResizeTarget::Pixels(30, 30), ResizeMode::Exact, (30, 30, 3)),
+            (ResizeTarget::Pixels(30, 30), ResizeMode::Fit, (30, 15, 3)),
+            (ResizeTarget::Pixels(30, 30), ResizeMode::Fill, (30, 30, 3)),
+            (ResizeTarget::Percent(50.0), ResizeMode::Exact, (20, 10, 3)),
+            (ResizeTarget::Percent(250.0), ResizeMode::Fit, (100, 50, 3)),
+        ] {
+            assert_eq!(resize(&img, target, ResampleKernel::Bilinear, mode).dim(), dims, "{:?}", mode);
+        }
+    }
+
+    // the weights of every kernel add up to one, so flat areas keep their color
+    #[test]
+    fn kernels_keep_uniform_color() {
+        let img = Array3::from_elem((17, 11, 3), 140);
+        for kernel in KERNELS {
+            for (width, height) in [(40, 7), (5, 30)] {
+                let res = resize(&img, ResizeTarget::Pixels(width, height), kernel, ResizeMode::Exact);
+                assert_eq!(res, Array3::from_elem((width, height, 3), 140), "{:?}", kernel);
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_repeats_pixels() {
+        let img = noise_image(9, 7, 34);
+        let res = resize(&img, ResizeTarget::Percent(200.0), ResampleKernel::Nearest, ResizeMode::Exact);
+        for ((x, y, c), &val) in res.indexed_iter() {
+            assert_eq!(val, img[[x / 2, y / 2, c]], "{}, {}", x, y);
+        }
+    }
+
+    // a one pixel checkerboard has no detail left at half size, antialiased kernels average it to gray
+    // while nearest picks one of its colors
+    #[test]
+    fn downscaling_is_antialiased() {
+        let img = Array3::from_shape_fn((32, 32, 3), |(x, y, _)| if (x + y) % 2 == 0 { 0 } else { 255 });
+        for kernel in KERNELS {
+            let res = resize(&img, ResizeTarget::Percent(50.0), kernel, ResizeMode::Exact);
+            let inside = res.slice(s![3..13, 3..13, ..]);
+            match kernel {
+                ResampleKernel::Nearest => assert!(inside.iter().all(|&v| v == 0 || v == 255)),
+                _ => assert!(inside.iter().all(|&v| v.abs_diff(128) <= 2), "{:?}", kernel),
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use ndarray::{Array3, s};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, Param, ParamKind, Form, Usage};
+use crate::color::{Rgb, parse_hex, to_hex};
+use crate::linalg::{BorderMode, Interpolation, sample, par_image_from_fn};
+
+pub struct Rotate {
+    deg: f64,
+    mode: RotateMode,
+    fill: Rgb,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RotateMode {
+    Expand,
+    Crop,
+}
+
+impl std::str::FromStr for RotateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "expand" => Ok(RotateMode::Expand),
+            "crop" => Ok(RotateMode::Crop),
+            _ => Err(format!("{} is not a valid rotation mode", s)),
+        }
+    }
+}
+
+impl Rotate {
+    /// Positive angles rotate clockwise.
+    pub fn new(deg: f64, mode: RotateMode, fill: Rgb) -> Self {
+        Self { deg: deg.rem_euclid(360.0), mode, fill }
+    }
+
+    fn rotate_arbitrary(&self, img: &Array3<u8>) -> Array3<u8> {
+        let (width, height, _) = img.dim();
+        let (sin, cos) = self.deg.to_radians().sin_cos();
+        let (res_width, res_height) = match self.mode {
+            RotateMode::Crop => (width, height),
+            RotateMode::Expand => (
+                (width as f64 * cos.abs() + height as f64 * sin.abs()).round() as usize,
+                (width as f64 * sin.abs() + height as f64 * cos.abs()).round() as usize,
+            ),
+        };
+
+        let (cx, cy) = ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0);
+        let (res_cx, res_cy) = ((res_width as f64 - 1.0) / 2.0, (res_height as f64 - 1.0) / 2.0);
+        par_image_from_fn(res_width.max(1), res_height.max(1), |x, y| {
+            // inverse mapping, each output pixel is sampled from the source image
+            let (dx, dy) = (x as f64 - res_cx, y as f64 - res_cy);
+            let src_x = cos * dx + sin * dy + cx;
+            let src_y = -sin * dx + cos * dy + cy;
+            sample(img, src_x, src_y, Interpolation::Bilinear, &BorderMode::Constant(self.fill)).map(|v| v.clamp(0.0, 255.0).round() as u8)
+        })
+    }
+}
+
+impl Manipulate for Rotate {
+    fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
+        // quarter turns are lossless and simply swap the image axes
+        if self.deg == 0.0 {
+            img.clone()
+        } else if self.deg == 90.0 {
+            let mut view = img.view();
+            view.swap_axes(0, 1);
+            view.slice(s![..;-1, .., ..]).to_owned()
+        } else if self.deg == 180.0 {
+            img.slice(s![..;-1, ..;-1, ..]).to_owned()
+        } else if self.deg == 270.0 {
+            let mut view = img.view();
+            view.swap_axes(0, 1);
+            view.slice(s![.., ..;-1, ..]).to_owned()
+        } else {
+            self.rotate_arbitrary(img)
+        }
+    }
+
+    fn details_str(&self) -> String {
+        format!("Rotate -> degrees: {}, mode: {:?}, fill: {}", self.deg, self.mode, to_hex(&self.fill))
+    }
+}
+
+const DEGREES: Param = Param::required("degrees", ParamKind::Number(f64::NEG_INFINITY, f64::INFINITY), "12.5");
+const MODE: Param = Param::optional("mode", ParamKind::Choice(&["expand", "crop"]), Some("expand"), "crop");
+const FILL: Param = Param::optional("fill color", ParamKind::Color, Some("#000000"), "#ffffff");
+
+impl CommandParse for Rotate {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let deg = DEGREES.parse::<f64>("rotate", arg(0))?;
+        let mode = MODE.parse::<RotateMode>("rotate", arg(1))?;
+        let fill = FILL.parse_with("rotate", arg(2), parse_hex)?;
+        Ok(Filter::Rotate(Rotate::new(deg, mode, fill)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "rotate",
+            forms: &[Form { keywords: "", params: &[DEGREES, MODE, FILL], description: "clockwise, multiples of 90 are lossless" }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgarray::noise_image;
+
+    fn rotate(img: &Array3<u8>, deg: f64, mode: RotateMode) -> Array3<u8> {
+        Rotate::new(deg, mode, [255, 0, 0]).apply(img)
+    }
+
+    #[test]
+    fn quarter_turns_move_pixels_exactly() {
+        let img = noise_image(30, 20, 32);
+        let res = rotate(&img, 90.0, RotateMode::Expand);
+        assert_eq!(res.dim(), (20, 30, 3));
+        // clockwise, the top left corner ends up in the top right one
+        assert_eq!(res.slice(s![19, 0, ..]), img.slice(s![0, 0, ..]));
+        assert_eq!(rotate(&res, 270.0, RotateMode::Expand), img);
+        assert_eq!(rotate(&rotate(&img, 180.0, RotateMode::Crop), -180.0, RotateMode::Crop), img);
+        assert_eq!(rotate(&img, 360.0, RotateMode::Crop), img);
+    }
+
+    // the uniform inside stays the same through bilinear sampling, the uncovered corners get the fill color
+    #[test]
+    fn arbitrary_angles_fill_uncovered_area() {
+        let img = Array3::from_elem((30, 20, 3), 90);
+        let expanded = rotate(&img, 45.0, RotateMode::Expand);
+        assert_eq!(expanded.dim(), (35, 35, 3));
+        let cropped = rotate(&img, 30.0, RotateMode::Crop);
+        assert_eq!(cropped.dim(), (30, 20, 3));
+        for res in [expanded, cropped] {
+            let (width, height, _) = res.dim();
+            assert_eq!(res.slice(s![width / 2, height / 2, ..]).to_vec(), vec![90; 3]);
+            assert_eq!(res.slice(s![0, 0, ..]).to_vec(), vec![255, 0, 0]);
+        }
+    }
+}
@@ -1,6 +1,13 @@
 use ndarray::{Array3, Axis, stack};
-use crate::filters::Manipulate;
-use crate::imgarray::AsImage;
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Form, Manipulate, Usage};
+use crate::filters::channelmixer::{ColorMatrix, mix_channels};
+
+pub const SEPIA_MATRIX: ColorMatrix = [
+    [0.393, 0.769, 0.189, 0.0],
+    [0.349, 0.686, 0.168, 0.0],
+    [0.272, 0.534, 0.131, 0.0],
+];
 
 #[derive(Default)]
 pub struct Sepia;
@@ -11,20 +18,33 @@ impl Sepia {
 
 impl Manipulate for Sepia {
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
-        let (r, g, b) = img.rgb_as_float();
-
-        let nr = &r * 0.393 + &g * 0.769 + &b * 0.189;
-        let ng = &r * 0.349 + &g * 0.686 + &b * 0.168;
-        let nb = &r * 0.272 + &g * 0.534 + &b * 0.131;
+        let (nr, ng, nb) = mix_channels(img, &SEPIA_MATRIX);
 
         stack(Axis(2), &[
-            nr.mapv(|x| x.min(255.0).max(0.0) as u8).view(),
-            ng.mapv(|x| x.min(255.0).max(0.0) as u8).view(),
-            nb.mapv(|x| x.min(255.0).max(0.0) as u8).view(),
+            nr.mapv(|x| x.clamp(0.0, 255.0) as u8).view(),
+            ng.mapv(|x| x.clamp(0.0, 255.0) as u8).view(),
+            nb.mapv(|x| x.clamp(0.0, 255.0) as u8).view(),
         ]).unwrap()
     }
 
     fn details_str(&self) -> String {
         "Sepia".to_string()
     }
-}
\ No newline at end of file
+
+    fn neighborhood(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+impl CommandParse for Sepia {
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        match command.iter().find(|s| !s.is_empty()) {
+            Some(s) => Err(MagrustteError::unexpected_argument("sepia", s)),
+            None => Ok(Filter::Sepia(Sepia::new())),
+        }
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "sepia", forms: &[Form { keywords: "", params: &[], description: "warm brownish tones" }] }
+    }
+}
@@ -1,13 +1,16 @@
 use ndarray::Array3;
-use crate::filters::{CommandParse, Filter, Manipulate, blur::{Blur, BlurMode}, bilateral::Bilateral};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, blur::{Blur, BlurMode}, bilateral::Bilateral, Param, ParamKind, Form, Usage};
+use crate::linalg::BorderMode;
 
 pub struct Sharpen {
     mode: SharpenMode,
     coarse_radius: i32,
     render_fine_mask: bool,
+    border: BorderMode,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum SharpenMode {
     Gaussian,
     Box,
@@ -32,54 +35,72 @@ impl std::str::FromStr for SharpenMode {
 impl Sharpen {
     pub fn new(mode: SharpenMode, coarse_radius: i32, render_fine_mask: bool) -> Self {
         // TODO: idk if all those params have to be user defined
-        Self { mode, coarse_radius, render_fine_mask }
+        Self { mode, coarse_radius, render_fine_mask, border: BorderMode::Clamp }
+    }
+
+    pub fn with_border(mut self, border: BorderMode) -> Self {
+        self.border = border;
+        self
+    }
+
+    fn coarse_filter(&self) -> Filter {
+        match self.mode {
+            SharpenMode::Gaussian => Filter::Blur(Blur::new(self.coarse_radius, BlurMode::Gaussian).with_border(self.border)),
+            SharpenMode::Box => Filter::Blur(Blur::new(self.coarse_radius, BlurMode::Box).with_border(self.border)),
+            SharpenMode::Median => Filter::Blur(Blur::new(self.coarse_radius, BlurMode::Median).with_border(self.border)),
+            SharpenMode::Bilateral =>
+                Filter::Bilateral(Bilateral::new(self.coarse_radius, self.coarse_radius as f64, 0.05).with_border(self.border)),
+        }
     }
 }
 
 // on sharpening: https://web.stanford.edu/class/cs448f/lectures/2.1/Sharpening.pdf
 impl Manipulate for Sharpen {
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
-        let coarse = match self.mode {
-            SharpenMode::Gaussian => Blur::new(self.coarse_radius, BlurMode::Gaussian).apply(img),
-            SharpenMode::Box => Blur::new(self.coarse_radius, BlurMode::Box).apply(img),
-            SharpenMode::Median => Blur::new(self.coarse_radius, BlurMode::Median).apply(img),
-            SharpenMode::Bilateral =>
-                Bilateral::new(self.coarse_radius, self.coarse_radius as f64, 0.05).apply(img),
-        };
+        let coarse = self.coarse_filter().apply(img);
 
         // fine = img - coarse (mapped to i32 to avoid underflow)
         let fine = img.mapv(|x| x as i32) - coarse.mapv(|x| x as i32);
 
         if self.render_fine_mask {
-            fine.mapv(|x| x.max(0).min(255) as u8)
+            fine.mapv(|x| x.clamp(0, 255) as u8)
         } else {
             (img.mapv(|x| x as i32) + &fine / 2)
-                .mapv(|x| x.max(0).min(255) as u8)
+                .mapv(|x| x.clamp(0, 255) as u8)
         }
     }
 
     fn details_str(&self) -> String {
-        format!("Sharpen -> mode: {:?}", self.mode)
+        format!("Sharpen -> mode: {:?}, border: {:?}", self.mode, self.border)
+    }
+
+    fn neighborhood(&self) -> Option<usize> {
+        self.coarse_filter().neighborhood()
+    }
+
+    // a zero radius would take the sharpening away entirely
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        let radius = ((self.coarse_radius as f64 * factor).round() as i32).max(1);
+        Some(Filter::Sharpen(Sharpen::new(self.mode, radius, self.render_fine_mask).with_border(self.border)))
     }
 }
 
+const MODE: Param = Param::required("mode", ParamKind::Choice(&["gaussian", "box", "median", "bilateral"]), "gaussian");
+const RADIUS: Param = Param::required("radius", ParamKind::Int(0, 50), "3");
+const FINE_MASK: Param = Param::required("render fine mask", ParamKind::Choice(&["true", "false"]), "false");
+const BORDER: Param = Param::optional("border", ParamKind::Border, Some("clamp"), "reflect");
+
 impl CommandParse for Sharpen {
-    fn parse(command: Vec<String>) -> Result<Filter, Box<dyn std::error::Error>> {
-        let maybe_mode = match command.get(0) {
-            Some(s) => s,
-            None => "nam",
-        };
-        let maybe_coarse_radius = match command.get(1) {
-            Some(s) => s,
-            None => "nan",
-        };
-        let maybe_render_fine_mask = match command.get(2) {
-            Some(s) => s,
-            None => "nab",
-        };
-        let mode = maybe_mode.parse::<SharpenMode>()?;
-        let coarse_radius = maybe_coarse_radius.parse::<i32>()?;
-        let render_fine_mask = maybe_render_fine_mask.parse::<bool>()?;
-        Ok(Filter::Sharpen(Sharpen::new(mode, coarse_radius, render_fine_mask)))
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let mode = MODE.parse::<SharpenMode>("sharpen", arg(0))?;
+        let coarse_radius = RADIUS.parse::<i32>("sharpen", arg(1))?;
+        let render_fine_mask = FINE_MASK.parse::<bool>("sharpen", arg(2))?;
+        let border = BORDER.parse::<BorderMode>("sharpen", arg(3))?;
+        Ok(Filter::Sharpen(Sharpen::new(mode, coarse_radius, render_fine_mask).with_border(border)))
+    }
+
+    fn usage() -> Usage {
+        Usage { name: "sharpen", forms: &[Form { keywords: "", params: &[MODE, RADIUS, FINE_MASK, BORDER], description: "" }] }
     }
 }
\ No newline at end of file
@@ -1,40 +1,281 @@
-use ndarray::{Array3, stack, Axis};
-use crate::filters::{CommandParse, Filter, Manipulate};
+use ndarray::{Array2, Array3, stack, Axis, Zip};
+use crate::error::MagrustteError;
+use crate::filters::{CommandParse, Filter, Manipulate, grayscale::Grayscale, Param, ParamKind, Form, Usage};
 use crate::imgarray::AsImage;
+use crate::color::{Rgb, parse_hex, to_hex};
+use crate::linalg::{centered_gaussian_kernel, convolve_separable, box_mean, hysteresis};
 
 pub struct Threshold {
-    threshold: u8,
+    method: ThresholdMethod,
+    per_channel: bool,
+    foreground: Rgb,
+    background: Rgb,
+}
+
+#[derive(Debug)]
+pub enum ThresholdMethod {
+    Manual(u8),
+    Otsu,
+    AdaptiveMean { block_size: i32, offset: f64 },
+    AdaptiveGaussian { block_size: i32, offset: f64 },
+    Hysteresis { low: u8, high: u8 },
 }
 
 impl Threshold {
     pub fn new(threshold: i32) -> Self {
-        Self { threshold: threshold.max(0).min(255) as u8  }
+        Self::with_method(ThresholdMethod::Manual(threshold.clamp(0, 255) as u8), false)
+    }
+
+    pub fn with_method(method: ThresholdMethod, per_channel: bool) -> Self {
+        let method = match method {
+            // block sizes have to be odd so that the window is centered on the pixel
+            ThresholdMethod::AdaptiveMean { block_size, offset } =>
+                ThresholdMethod::AdaptiveMean { block_size: block_size.clamp(3, 101) | 1, offset },
+            ThresholdMethod::AdaptiveGaussian { block_size, offset } =>
+                ThresholdMethod::AdaptiveGaussian { block_size: block_size.clamp(3, 101) | 1, offset },
+            ThresholdMethod::Hysteresis { low, high } =>
+                ThresholdMethod::Hysteresis { low: low.min(high), high: low.max(high) },
+            _ => method,
+        };
+        Self { method, per_channel, foreground: [255, 255, 255], background: [0, 0, 0] }
+    }
+
+    pub fn with_colors(mut self, foreground: Rgb, background: Rgb) -> Self {
+        self.foreground = foreground;
+        self.background = background;
+        self
+    }
+
+    /// <https://en.wikipedia.org/wiki/Otsu%27s_method>
+    pub fn otsu_level(channel: &Array2<f64>) -> u8 {
+        let mut histogram = [0usize; 256];
+        channel.iter().for_each(|&x| histogram[x as u8 as usize] += 1);
+
+        let total = channel.len() as f64;
+        let sum_all: f64 = histogram.iter().enumerate().map(|(i, &n)| i as f64 * n as f64).sum();
+        let (mut weight_bg, mut sum_bg) = (0.0, 0.0);
+        let (mut best_level, mut best_variance) = (0, 0.0);
+
+        for (level, &count) in histogram.iter().enumerate() {
+            weight_bg += count as f64;
+            if weight_bg == 0.0 {
+                continue;
+            }
+            let weight_fg = total - weight_bg;
+            if weight_fg == 0.0 {
+                break;
+            }
+            sum_bg += level as f64 * count as f64;
+            let mean_bg = sum_bg / weight_bg;
+            let mean_fg = (sum_all - sum_bg) / weight_fg;
+            let variance = weight_bg * weight_fg * (mean_bg - mean_fg).powi(2);
+            if variance > best_variance {
+                best_level = level;
+                best_variance = variance;
+            }
+        }
+        best_level as u8
+    }
+
+    // true where the pixel belongs to the foreground
+    fn mask(&self, channel: &Array2<f64>) -> Array2<bool> {
+        match self.method {
+            ThresholdMethod::Manual(level) => channel.mapv(|x| x as u8 > level),
+            ThresholdMethod::Otsu => {
+                let level = Self::otsu_level(channel);
+                channel.mapv(|x| x as u8 > level)
+            },
+            // https://docs.opencv.org/4.x/d7/d1b/group__imgproc__misc.html#ga72b913f352e4a1b1b397736707afcde3
+            ThresholdMethod::AdaptiveMean { block_size, offset } => {
+                let local = box_mean(channel, block_size / 2);
+                Zip::from(channel).and(&local).map_collect(|&x, &m| x > m - offset)
+            },
+            ThresholdMethod::AdaptiveGaussian { block_size, offset } => {
+                let sigma = 0.3 * ((block_size - 1) as f64 * 0.5 - 1.0) + 0.8;
+                let kernel = centered_gaussian_kernel(block_size, sigma);
+                let local = convolve_separable(channel, &kernel, &kernel);
+                Zip::from(channel).and(&local).map_collect(|&x, &m| x > m - offset)
+            },
+            ThresholdMethod::Hysteresis { low, high } => hysteresis(channel, low as f64, high as f64),
+        }
     }
 }
 
 impl Manipulate for Threshold {
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
-        let (r, g, b) = img.split_channels();
-        let r_f = r.mapv(|x| x as f64);
-        let g_f = g.mapv(|x| x as f64);
-        let b_f = b.mapv(|x| x as f64);
-        let luma = r_f * 0.2126 + g_f * 0.7152 + b_f * 0.0722; // https://en.wikipedia.org/wiki/Grayscale#Colorimetric_(perceptual_luminance-preserving)_conversion_to_grayscale
-        let effect = luma.mapv(|x| if x as u8 > self.threshold { 255 } else { 0 });
-        stack(Axis(2), &[effect.view(), effect.view(), effect.view()]).unwrap()
+        let masks = if self.per_channel {
+            let (r, g, b) = img.rgb_as_float();
+            [self.mask(&r), self.mask(&g), self.mask(&b)]
+        } else {
+            let luma = self.mask(&Grayscale::new().luma(img));
+            [luma.clone(), luma.clone(), luma]
+        };
+
+        let effect = |c: usize| masks[c].mapv(|x| if x { self.foreground[c] } else { self.background[c] });
+        stack(Axis(2), &[effect(0).view(), effect(1).view(), effect(2).view()]).unwrap()
     }
 
     fn details_str(&self) -> String {
-        format!("Threshold -> threshold: {}", self.threshold)
+        let method = match self.method {
+            ThresholdMethod::Manual(level) => format!("threshold: {}", level),
+            _ => format!("method: {:?}", self.method),
+        };
+        let per_channel = if self.per_channel { ", per channel" } else { "" };
+        let colors = if self.foreground != [255, 255, 255] || self.background != [0, 0, 0] {
+            format!(", colors: {} on {}", to_hex(&self.foreground), to_hex(&self.background))
+        } else {
+            "".to_string()
+        };
+        format!("Threshold -> {}{}{}", method, per_channel, colors)
+    }
+
+    fn neighborhood(&self) -> Option<usize> {
+        match self.method {
+            ThresholdMethod::Manual(_) => Some(0),
+            ThresholdMethod::AdaptiveMean { block_size, .. } => Some(block_size as usize / 2),
+            ThresholdMethod::AdaptiveGaussian { block_size, .. } => Some(block_size as usize / 2),
+            // the level comes from the histogram of the whole image, and connected edges can run across it
+            ThresholdMethod::Otsu | ThresholdMethod::Hysteresis { .. } => None,
+        }
+    }
+
+    // only the adaptive window depends on the image size, with_method keeps the scaled block size odd and at least 3
+    fn scaled(&self, factor: f64) -> Option<Filter> {
+        let scale = |block_size: i32| (block_size as f64 * factor).round() as i32;
+        let method = match self.method {
+            ThresholdMethod::AdaptiveMean { block_size, offset } =>
+                ThresholdMethod::AdaptiveMean { block_size: scale(block_size), offset },
+            ThresholdMethod::AdaptiveGaussian { block_size, offset } =>
+                ThresholdMethod::AdaptiveGaussian { block_size: scale(block_size), offset },
+            _ => return None,
+        };
+        Some(Filter::Threshold(Threshold::with_method(method, self.per_channel)
+            .with_colors(self.foreground, self.background)))
     }
 }
 
+const THRESHOLD: Param = Param::required("value", ParamKind::Int(0, 255), "128");
+const ADAPTIVE_MODE: Param = Param::required("mode", ParamKind::Choice(&["mean", "gaussian"]), "mean");
+const BLOCK_SIZE: Param = Param::required("block size", ParamKind::Int(3, 101), "15");
+const OFFSET: Param = Param::required("offset", ParamKind::Number(f64::NEG_INFINITY, f64::INFINITY), "5");
+const LOW: Param = Param::required("low", ParamKind::Int(0, 255), "50");
+const HIGH: Param = Param::required("high", ParamKind::Int(0, 255), "150");
+const PER_CHANNEL: Param = Param::keyword("perchannel", &[]);
+const FOREGROUND: Param = Param::required("foreground", ParamKind::Color, "#ffffff");
+const BACKGROUND: Param = Param::required("background", ParamKind::Color, "#203040");
+const COLORS: Param = Param::keyword("colors", &[FOREGROUND, BACKGROUND]);
+
 impl CommandParse for Threshold {
-    fn parse(command: Vec<String>) -> Result<Filter, Box<dyn std::error::Error>> {
-        let maybe_threshold = match command.get(0) {
-            Some(s) => s,
-            None => "nan",
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let mut args = command.iter().map(|s| s.as_str()).filter(|s| !s.is_empty());
+
+        let method = match args.next().unwrap_or("") {
+            "otsu" => ThresholdMethod::Otsu,
+            "adaptive" => {
+                let gaussian = ADAPTIVE_MODE.parse_with("threshold", args.next().unwrap_or(""), |s| match s {
+                    "mean" => Ok(false),
+                    "gaussian" => Ok(true),
+                    _ => Err(()),
+                })?;
+                let block_size = BLOCK_SIZE.parse::<i32>("threshold", args.next().unwrap_or(""))?;
+                let offset = OFFSET.parse::<f64>("threshold", args.next().unwrap_or(""))?;
+                match gaussian {
+                    false => ThresholdMethod::AdaptiveMean { block_size, offset },
+                    true => ThresholdMethod::AdaptiveGaussian { block_size, offset },
+                }
+            },
+            "hysteresis" => {
+                let low = LOW.parse::<u8>("threshold", args.next().unwrap_or(""))?;
+                let high = HIGH.parse::<u8>("threshold", args.next().unwrap_or(""))?;
+                ThresholdMethod::Hysteresis { low, high }
+            },
+            maybe_threshold => ThresholdMethod::Manual(THRESHOLD.parse::<u8>("threshold", maybe_threshold)?),
         };
-        let threshold = maybe_threshold.parse::<i32>()?;
-        Ok(Filter::Threshold(Threshold::new(threshold)))
+
+        let mut per_channel = false;
+        let (mut foreground, mut background) = ([255, 255, 255], [0, 0, 0]);
+        while let Some(option) = args.next() {
+            match option {
+                "perchannel" => per_channel = true,
+                "colors" => {
+                    foreground = FOREGROUND.parse_with("threshold", args.next().unwrap_or(""), parse_hex)?;
+                    background = BACKGROUND.parse_with("threshold", args.next().unwrap_or(""), parse_hex)?;
+                },
+                _ => return Err(MagrustteError::unexpected_argument("threshold", option)),
+            }
+        }
+
+        Ok(Filter::Threshold(Threshold::with_method(method, per_channel).with_colors(foreground, background)))
     }
-}
\ No newline at end of file
+
+    fn usage() -> Usage {
+        Usage {
+            name: "threshold",
+            forms: &[
+                Form { keywords: "", params: &[THRESHOLD, PER_CHANNEL, COLORS], description: "" },
+                Form { keywords: "otsu", params: &[PER_CHANNEL, COLORS], description: "picks the threshold from the histogram" },
+                Form {
+                    keywords: "adaptive",
+                    params: &[ADAPTIVE_MODE, BLOCK_SIZE, OFFSET, PER_CHANNEL, COLORS],
+                    description: "compares every pixel with its neighborhood",
+                },
+                Form { keywords: "hysteresis", params: &[LOW, HIGH, PER_CHANNEL, COLORS], description: "keeps weak pixels connected to strong ones" },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgarray::noise_image;
+
+    // noise around 40 on the left half and around 200 on the right half
+    #[test]
+    fn otsu_splits_bimodal_image() {
+        let noise = noise_image(40, 30, 28);
+        let img = Array3::from_shape_fn((40, 30, 3), |(x, y, _)| {
+            let base = if x < 20 { 20 } else { 180 };
+            base + noise[[x, y, 0]] / 6
+        });
+        let level = Threshold::otsu_level(&img.rgb_as_float().0);
+        assert!((62..180).contains(&level), "level {}", level);
+
+        let res = Threshold::with_method(ThresholdMethod::Otsu, false).apply(&img);
+        for ((x, _, _), &val) in res.indexed_iter() {
+            assert_eq!(val, if x < 20 { 0 } else { 255 }, "column {}", x);
+        }
+    }
+
+    // a darker column on a gradient is the only part darker than its neighborhood, which no global level can find
+    #[test]
+    fn adaptive_finds_line_on_gradient() {
+        let channel = Array2::from_shape_fn((40, 12), |(x, _)| 100.0 + 2.0 * x as f64 - if x == 20 { 40.0 } else { 0.0 });
+        for method in [
+            ThresholdMethod::AdaptiveMean { block_size: 7, offset: 5.0 },
+            ThresholdMethod::AdaptiveGaussian { block_size: 7, offset: 5.0 },
+        ] {
+            let mask = Threshold::with_method(method, false).mask(&channel);
+            for ((x, y), &foreground) in mask.indexed_iter() {
+                assert_eq!(foreground, x != 20, "{}, {}", x, y);
+            }
+        }
+    }
+
+    // weak pixels are kept when they touch a strong one, even diagonally, and dropped otherwise
+    #[test]
+    fn hysteresis_keeps_connected_weak_pixels() {
+        let mut channel = Array2::<f64>::zeros((10, 6));
+        channel[[1, 1]] = 200.0;
+        for (x, y) in [(2, 2), (3, 2), (4, 3)] {
+            channel[[x, y]] = 100.0;
+        }
+        for (x, y) in [(7, 1), (8, 1)] {
+            channel[[x, y]] = 100.0;
+        }
+        channel[[5, 3]] = 40.0;
+        let mask = Threshold::with_method(ThresholdMethod::Hysteresis { low: 50, high: 150 }, false).mask(&channel);
+        let kept = mask.indexed_iter().filter(|(_, &kept)| kept).map(|(index, _)| index).collect::<Vec<_>>();
+        assert_eq!(kept, vec![(1, 1), (2, 2), (3, 2), (4, 3)]);
+    }
+}
@@ -1,58 +1,88 @@
-use ndarray::{Array3, stack, Axis};
-use ndarray_stats::QuantileExt;
-use crate::filters::{Manipulate, CommandParse, Filter};
-use crate::imgarray::AsImage;
-use crate::linalg::{gaussian_kernel, outer_product};
+use ndarray::Array3;
+use crate::error::MagrustteError;
+use crate::filters::{Manipulate, CommandParse, Filter, Param, ParamKind, Form, Usage};
+use crate::linalg::{gaussian_kernel, outer_product, par_image_from_fn};
 
 pub struct Vignette {
     radius: f64,
     opacity: f64,
+    mode: VignetteMode,
+}
+
+#[derive(Debug)]
+pub enum VignetteMode {
+    Add,
+    Remove, // divides by the same falloff, to correct lens vignetting instead of adding it
+}
+
+impl std::str::FromStr for VignetteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "add" => Ok(VignetteMode::Add),
+            "devignette" | "remove" => Ok(VignetteMode::Remove),
+            _ => Err(format!("{} is not a valid vignette mode", s)),
+        }
+    }
 }
 
 impl Vignette {
     pub fn new(radius: i32, opacity: i32) -> Self {
+        Self::with_mode(radius, opacity, VignetteMode::Add)
+    }
+
+    pub fn with_mode(radius: i32, opacity: i32, mode: VignetteMode) -> Self {
         Self {
-            radius: radius.max(0).min(100) as f64 / 100.0,
-            opacity: opacity.max(0).min(100) as f64 / 100.0,
+            // a radius of 0 would be a gaussian with no width at all
+            radius: radius.clamp(1, 100) as f64 / 100.0,
+            opacity: opacity.clamp(0, 100) as f64 / 100.0,
+            mode,
         }
     }
 }
 
 impl Manipulate for Vignette {
     fn apply(&mut self, img: &Array3<u8>) -> Array3<u8> {
-        let (rc , gc, bc) = img.rgb_as_float();
-
         let (width, height, _) = img.dim();
         let a = gaussian_kernel(width as i32, width as f64 * self.radius);
         let b = gaussian_kernel(height as i32, height as f64 * self.radius);
         let c = outer_product(&a, &b);
-        let d = &c / *c.max().unwrap();
-        let e = d.mapv(|x| (x + 1.0 - self.opacity).min(1.0).max(0.0));
-
-        stack(Axis(2), &[
-            (rc * &e).mapv(|x| x.min(255.0).max(0.0) as u8).view(),
-            (gc * &e).mapv(|x| x.min(255.0).max(0.0) as u8).view(),
-            (bc * &e).mapv(|x| x.min(255.0).max(0.0) as u8).view(),
-        ]).unwrap()
+        let d = &c / c.fold(0.0, |max, &x| f64::max(max, x));
+        let e = d.mapv(|x| (x + 1.0 - self.opacity).clamp(0.0, 1.0));
+        let e = match self.mode {
+            VignetteMode::Add => e,
+            VignetteMode::Remove => e.mapv(|x| 1.0 / x.max(0.01)),
+        };
+
+        par_image_from_fn(width, height, |x, y| {
+            let shade = |c: usize| (img[[x, y, c]] as f64 * e[[x, y]]).clamp(0.0, 255.0) as u8;
+            [shade(0), shade(1), shade(2)]
+        })
     }
 
     fn details_str(&self) -> String {
-        format!("Vignette -> radius: {}%, opacity: {}%", self.radius * 100.0, self.opacity * 100.0)
+        format!("Vignette -> radius: {}%, opacity: {}%, mode: {:?}", self.radius * 100.0, self.opacity * 100.0, self.mode)
     }
 }
 
+const RADIUS: Param = Param::required("radius", ParamKind::Int(1, 100), "50");
+const OPACITY: Param = Param::required("opacity", ParamKind::Int(0, 100), "80");
+const MODE: Param = Param::optional("mode", ParamKind::Choice(&["add", "remove"]), Some("add"), "remove");
+
 impl CommandParse for Vignette {
-    fn parse(command: Vec<String>) -> Result<Filter, Box<dyn std::error::Error>> {
-        let maybe_radius = match command.get(0) {
-            Some(s) => s,
-            None => "nan",
-        };
-        let maybe_opacity = match command.get(1) {
-            Some(s) => s,
-            None => "nan",
-        };
-        let radius = maybe_radius.parse::<i32>()?;
-        let opacity = maybe_opacity.parse::<i32>()?;
-        Ok(Filter::Vignette(Vignette::new(radius, opacity)))
+    fn parse(command: Vec<String>) -> Result<Filter, MagrustteError> {
+        let arg = |i: usize| command.get(i).map_or("", |s| s.as_str());
+        let radius = RADIUS.parse::<i32>("vignette", arg(0))?;
+        let opacity = OPACITY.parse::<i32>("vignette", arg(1))?;
+        let mode = MODE.parse::<VignetteMode>("vignette", arg(2))?;
+        Ok(Filter::Vignette(Vignette::with_mode(radius, opacity, mode)))
+    }
+
+    fn usage() -> Usage {
+        Usage {
+            name: "vignette",
+            forms: &[Form { keywords: "", params: &[RADIUS, OPACITY, MODE], description: "radius and opacity in percent" }],
+        }
     }
 }
\ No newline at end of file
@@ -1,21 +1,44 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 use ndarray::{Array2, Array3, s};
-use image::{ImageBuffer, GenericImageView, Rgb, RgbImage};
+use image::{ImageBuffer, ImageError, GenericImageView, Rgb, RgbImage};
+use crate::error::MagrustteError;
 
+/// Conversions between `Array3<u8>` images indexed by `[x, y, channel]` and image files.
 pub trait AsImage {
-    fn save(&self, path: &str);
-    fn read(path: &str) -> Result<Array3<u8>, Box<dyn std::error::Error>>;
+    /// Saves in the format of the path's extension, images with at most 256 colors are saved as indexed png and gif.
+    fn save(&self, path: &str) -> Result<(), MagrustteError>;
+    /// Reads any format the image crate supports, dropping the alpha channel.
+    fn read(path: &str) -> Result<Array3<u8>, MagrustteError>;
     fn to_rgb_image(&self) -> RgbImage;
+    /// Red, green and blue channels with values in 0..255.
     fn rgb_as_float(&self) -> (Array2<f64>, Array2<f64>, Array2<f64>);
     fn split_channels(&self) -> (Array2<u8>, Array2<u8>, Array2<u8>);
+    /// FNV-1a over the dimensions and pixels, stable across builds so it can be compared between versions.
+    /// <https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function>
+    fn content_hash(&self) -> u64;
 }
 
 impl AsImage for Array3<u8> {
-    fn save(&self, path: &str) {
-        self.to_rgb_image().save(path).unwrap();
+    fn save(&self, path: &str) -> Result<(), MagrustteError> {
+        let is_format = |name: &str| Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case(name));
+        match palette_indices(self) {
+            Some((palette, indices)) if is_format("png") => save_indexed_png(self, path, &palette, &indices),
+            Some((palette, indices)) if is_format("gif") => save_indexed_gif(self, path, &palette, &indices),
+            _ => self.to_rgb_image().save(path).map_err(|e| match e {
+                ImageError::IoError(source) => MagrustteError::Io { path: path.to_string(), source },
+                e => MagrustteError::Encode { path: path.to_string(), message: e.to_string() },
+            }),
+        }
     }
 
-    fn read(path: &str) -> Result<Array3<u8>, Box<dyn std::error::Error>> {
-        let img = image::open(path)?;
+    fn read(path: &str) -> Result<Array3<u8>, MagrustteError> {
+        let img = image::open(path).map_err(|e| match e {
+            ImageError::IoError(source) => MagrustteError::Io { path: path.to_string(), source },
+            e => MagrustteError::Decode { path: path.to_string(), message: e.to_string() },
+        })?;
         let (width, height) = img.dimensions();
         let mut res = Array3::<u8>::zeros((width as usize, height as usize, 3));
         for (x, y, pixel) in img.pixels() {
@@ -49,4 +72,122 @@ impl AsImage for Array3<u8> {
             self.slice(s![..,..,2]).to_owned(), // blue
         )
     }
-}
\ No newline at end of file
+
+    fn content_hash(&self) -> u64 {
+        let (width, height, _) = self.dim();
+        let dims = (width as u64).to_le_bytes().into_iter().chain((height as u64).to_le_bytes());
+        dims.chain(self.iter().copied()).fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+}
+
+// palette and per pixel indices in row-major order, None if the image has more than 256 colors
+fn palette_indices(img: &Array3<u8>) -> Option<(Vec<[u8; 3]>, Vec<u8>)> {
+    let (width, height, _) = img.dim();
+    let mut palette = Vec::<[u8; 3]>::new();
+    let mut lookup = HashMap::<[u8; 3], u8>::new();
+    let mut indices = Vec::<u8>::with_capacity(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let color = [img[[x, y, 0]], img[[x, y, 1]], img[[x, y, 2]]];
+            let index = match lookup.get(&color) {
+                Some(&index) => index,
+                None => {
+                    if palette.len() == 256 {
+                        return None;
+                    }
+                    palette.push(color);
+                    lookup.insert(color, (palette.len() - 1) as u8);
+                    (palette.len() - 1) as u8
+                },
+            };
+            indices.push(index);
+        }
+    }
+    Some((palette, indices))
+}
+
+// indexed png with the smallest bit depth that fits the palette, considerably smaller than rgb output
+fn save_indexed_png(img: &Array3<u8>, path: &str, palette: &[[u8; 3]], indices: &[u8]) -> Result<(), MagrustteError> {
+    let (width, height, _) = img.dim();
+    let (depth, bits) = match palette.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+
+    // rows are packed most significant bits first and padded to whole bytes
+    let row_bytes = (width * bits).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * height];
+    for (y, row) in indices.chunks(width).enumerate() {
+        for (x, &index) in row.iter().enumerate() {
+            let bit = x * bits;
+            data[y * row_bytes + bit / 8] |= index << (8 - bits - bit % 8);
+        }
+    }
+
+    let encoding_error = |e: png::EncodingError| match e {
+        png::EncodingError::IoError(source) => MagrustteError::Io { path: path.to_string(), source },
+        e => MagrustteError::Encode { path: path.to_string(), message: e.to_string() },
+    };
+    let file = File::create(path).map_err(|source| MagrustteError::Io { path: path.to_string(), source })?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(palette.concat());
+    let mut writer = encoder.write_header().map_err(encoding_error)?;
+    writer.write_image_data(&data).map_err(encoding_error)
+}
+
+// written with the palette found while checking the colors, instead of letting the image crate convert the image to rgba
+// and collect its colors all over again
+fn save_indexed_gif(img: &Array3<u8>, path: &str, palette: &[[u8; 3]], indices: &[u8]) -> Result<(), MagrustteError> {
+    let (width, height, _) = img.dim();
+    let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(MagrustteError::Encode {
+            path: path.to_string(),
+            message: "gif images are at most 65535 pixels wide and high".to_string(),
+        }),
+    };
+
+    let encoding_error = |e: gif::EncodingError| match e {
+        gif::EncodingError::Io(source) => MagrustteError::Io { path: path.to_string(), source },
+        e => MagrustteError::Encode { path: path.to_string(), message: e.to_string() },
+    };
+    let file = File::create(path).map_err(|source| MagrustteError::Io { path: path.to_string(), source })?;
+    let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &palette.concat()).map_err(encoding_error)?;
+    encoder.write_frame(&gif::Frame::from_indexed_pixels(width, height, indices, None)).map_err(encoding_error)
+}
+
+// deterministic noise for tests, xorshift so it doesn't need a random number crate
+#[cfg(test)]
+pub(crate) fn noise_image(width: usize, height: usize, seed: u64) -> Array3<u8> {
+    let mut state = seed.max(1);
+    Array3::from_shape_simple_fn((width, height, 3), || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 56) as u8
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a few levels per channel give images with 8, 64 and more than 256 colors
+    #[test]
+    fn indexed_formats_keep_exact_colors() {
+        for (levels, format) in [(2, "png"), (4, "png"), (8, "png"), (2, "gif"), (4, "gif")] {
+            let step = 256 / levels as u16;
+            let img = noise_image(45, 31, 30).mapv(|v| (v as u16 / step * step) as u8);
+            let path = std::env::temp_dir().join(format!("magrustte_indexed_{}.{}", levels, format));
+            let path = path.to_str().unwrap();
+            img.save(path).unwrap();
+            assert_eq!(Array3::<u8>::read(path).unwrap(), img, "{} levels as {}", levels, format);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Image manipulation as a composition of filters applied in a pipeline-like manner.
+//!
+//! Images are `Array3<u8>` arrays indexed by `[x, y, channel]`, with RGB channels. [`imgarray::AsImage`] reads and
+//! saves them. Every filter implements [`Manipulate`], and a [`Compose`] chains filters and caches its result, so
+//! adding a filter only renders the new one.
+//!
+//! ```no_run
+//! use ndarray::Array3;
+//! use magrustte::{Compose, Filter, Manipulate};
+//! use magrustte::filters::blur::{Blur, BlurMode};
+//! use magrustte::filters::sepia::Sepia;
+//! use magrustte::imgarray::AsImage;
+//!
+//! # fn main() -> Result<(), magrustte::MagrustteError> {
+//! let img = Array3::<u8>::read("input.jpg")?;
+//! let mut pipeline = Compose::builder()
+//!     .filter(Filter::Blur(Blur::new(3, BlurMode::Gaussian)))
+//!     .filter(Filter::Sepia(Sepia::new()))
+//!     .build();
+//! pipeline.apply(&img).save("output.png")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Failures are reported as a [`MagrustteError`], whose message can be shown to the user as it is.
+//!
+//! Filters can also be parsed from the arguments of the app's commands with [`CommandParse`]. The image window and
+//! file dialog of the app are behind the `gui` feature, which the library doesn't need.
+
+pub mod imgarray;
+pub mod filters;
+pub mod linalg;
+pub mod color;
+pub mod progress;
+pub mod error;
+
+pub use filters::{Filter, Manipulate, CommandParse};
+pub use filters::compose::{Compose, ComposeBuilder};
+pub use error::MagrustteError;
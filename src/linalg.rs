@@ -1,12 +1,17 @@
-use ndarray::{Array1, Array2};
+use ndarray::{Array1, Array2, Array3, ArrayViewMut1, Axis};
+use rayon::prelude::*;
 use std::cmp::Ordering;
+use crate::color::parse_hex;
+use crate::progress::Reporter;
 
+/// Normal distribution density at x.
 pub fn  gaussian(x: f64, mu: f64, sigma: f64) -> f64 {
     let a = 1.0 / (sigma * (2.0 * std::f64::consts::PI).sqrt());
     let b = -0.5 * ((x - mu) / sigma).powi(2);
     a * b.exp()
 }
 
+/// Gaussian weights over size values, normalized to sum to 1.
 pub fn gaussian_kernel(size: i32, sigma: f64) -> Array1<f64> {
     let mu = size / 2;
     let mut kernel = Array1::<f64>::linspace(0.0, size as f64, size as usize);
@@ -14,8 +19,18 @@ pub fn gaussian_kernel(size: i32, sigma: f64) -> Array1<f64> {
     &kernel / kernel.sum()
 }
 
-// necessary because ndarray doesn't support matrix multiplication between a row vector and a column vector
-// i.e. it "does" but returns a dot product instead of an outer product
+/// Same as gaussian_kernel, sampled at integer offsets so the kernel stays centered.
+/// gaussian_kernel is stretched over size + 1 units, which shifts its results slightly, it's kept as it is so the
+/// filters using it render the same as before.
+pub fn centered_gaussian_kernel(size: i32, sigma: f64) -> Array1<f64> {
+    let mu = (size - 1) as f64 / 2.0;
+    let kernel = Array1::<f64>::linspace(0.0, (size - 1) as f64, size as usize).mapv(|x| gaussian(x, mu, sigma));
+    &kernel / kernel.sum()
+}
+
+/// Outer product of two vectors, e.g. a 2d kernel from a 1d one.
+/// Necessary because ndarray doesn't support matrix multiplication between a row vector and a column vector,
+/// i.e. it "does" but returns a dot product instead of an outer product.
 pub fn outer_product(x: &Array1<f64>, y: &Array1<f64>) -> Array2<f64> {
     let (size_x, size_y) = (x.shape()[0], y.shape()[0]);
     let x_reshaped = x.view().into_shape((size_x, 1)).unwrap();
@@ -23,27 +38,595 @@ pub fn outer_product(x: &Array1<f64>, y: &Array1<f64>) -> Array2<f64> {
     x_reshaped.dot(&y_reshaped)
 }
 
-pub fn median(numbers: &mut Vec<f64>) -> f64 {
+/// Median of the numbers, which are sorted in place.
+pub fn median(numbers: &mut [f64]) -> f64 {
     numbers.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
     let mid = numbers.len() / 2;
-    if numbers.len() % 2 == 0 {
+    if numbers.len().is_multiple_of(2) {
         (numbers[mid - 1] + numbers[mid]) / 2.0
     } else {
         numbers[mid]
     }
 }
 
-pub fn array_slice(array: &Array2<f64>, x: i32, y: i32, radius: i32) -> Array2<f64> {
+/// (2 * radius + 1)² window centered on x, y, fill is the value of constant borders.
+pub fn array_slice(array: &Array2<f64>, x: i32, y: i32, radius: i32, border: &BorderMode, fill: f64) -> Array2<f64> {
     let (width, height) = array.dim();
     let mut res = Array2::<f64>::zeros((radius as usize * 2 + 1, radius as usize * 2 + 1));
 
-    for i in -radius..radius {
-        for j in -radius..radius {
-            let x_ = (x + i).max(0).min(width as i32 - 1);
-            let y_ = (y + j).max(0).min(height as i32 - 1);
+    for i in -radius..=radius {
+        for j in -radius..=radius {
+            let x_ = border.resolve((x + i) as i64, width);
+            let y_ = border.resolve((y + j) as i64, height);
 
-            res[[(radius + i) as usize, (radius + j) as usize]] = array[[x_ as usize, y_ as usize]];
+            res[[(radius + i) as usize, (radius + j) as usize]] = match (x_, y_) {
+                (Some(x_), Some(y_)) => array[[x_, y_]],
+                _ => fill,
+            };
         }
     }
     res
-}
\ No newline at end of file
+}
+
+/// 2d convolution with a kernel of odd dimensions centered on each pixel, edge pixels are repeated past the boundaries.
+/// Kernels are applied as they are written, without flipping them, i.e. strictly speaking a correlation.
+pub fn convolve(array: &Array2<f64>, kernel: &Array2<f64>) -> Array2<f64> {
+    convolve_bordered(array, kernel, &BorderMode::Clamp, 0.0)
+}
+
+/// Array built value by value, columns are computed in parallel on the current thread pool.
+/// Every value is computed the same way whatever the thread count, so the results are identical.
+/// Finished columns are reported to the render in progress, and once it's cancelled the remaining ones are skipped.
+pub fn par_from_fn(width: usize, height: usize, f: impl Fn(usize, usize) -> f64 + Sync) -> Array2<f64> {
+    let mut res = Array2::<f64>::zeros((width, height));
+    let reporter = Reporter::current();
+    reporter.start_pass(width);
+    res.axis_iter_mut(Axis(0)).into_par_iter().enumerate().for_each(|(x, mut column)| {
+        if reporter.is_cancelled() {
+            return;
+        }
+        column.iter_mut().enumerate().for_each(|(y, val)| *val = f(x, y));
+        reporter.line_done();
+    });
+    res
+}
+
+/// Same as par_from_fn for rgb images.
+pub fn par_image_from_fn(width: usize, height: usize, f: impl Fn(usize, usize) -> [u8; 3] + Sync) -> Array3<u8> {
+    let mut res = Array3::<u8>::zeros((width, height, 3));
+    let reporter = Reporter::current();
+    reporter.start_pass(width);
+    res.axis_iter_mut(Axis(0)).into_par_iter().enumerate().for_each(|(x, mut column)| {
+        if reporter.is_cancelled() {
+            return;
+        }
+        for (y, mut pixel) in column.axis_iter_mut(Axis(0)).enumerate() {
+            let val = f(x, y);
+            (0..3).for_each(|c| pixel[c] = val[c]);
+        }
+        reporter.line_done();
+    });
+    res
+}
+
+/// Runs f on every line of the array along the axis, i.e. columns for Axis(1) and rows for Axis(0), in parallel.
+pub fn par_for_each_line(array: &mut Array2<f64>, axis: Axis, f: impl Fn(usize, ArrayViewMut1<f64>) + Sync) {
+    let reporter = Reporter::current();
+    reporter.start_pass(array.len_of(Axis(1 - axis.index())));
+    array.axis_iter_mut(Axis(1 - axis.index())).into_par_iter().enumerate().for_each(|(i, line)| {
+        if reporter.is_cancelled() {
+            return;
+        }
+        f(i, line);
+        reporter.line_done();
+    });
+}
+
+// resolved index for every position from -radius to len + radius, None where the constant border applies
+fn border_indices(len: usize, radius: usize, border: &BorderMode) -> Vec<Option<usize>> {
+    (-(radius as i64)..(len + radius) as i64).map(|i| border.resolve(i, len)).collect()
+}
+
+/// Array extended by pad on every side according to the border mode.
+pub fn pad_array(array: &Array2<f64>, pad: usize, border: &BorderMode, fill: f64) -> Array2<f64> {
+    let (width, height) = array.dim();
+    let (indices_x, indices_y) = (border_indices(width, pad, border), border_indices(height, pad, border));
+    Array2::from_shape_fn((width + 2 * pad, height + 2 * pad), |(x, y)| match (indices_x[x], indices_y[y]) {
+        (Some(x_), Some(y_)) => array[[x_, y_]],
+        _ => fill,
+    })
+}
+
+/// Same as convolve, fill is the value of constant borders for this channel.
+pub fn convolve_bordered(array: &Array2<f64>, kernel: &Array2<f64>, border: &BorderMode, fill: f64) -> Array2<f64> {
+    let (width, height) = array.dim();
+    let (radius_x, radius_y) = (kernel.dim().0 / 2, kernel.dim().1 / 2);
+    let (indices_x, indices_y) = (border_indices(width, radius_x, border), border_indices(height, radius_y, border));
+
+    par_from_fn(width, height, |x, y| {
+        let mut new_val = 0.0;
+        for ((i, j), k) in kernel.indexed_iter() {
+            new_val += match (indices_x[x + i], indices_y[y + j]) {
+                (Some(x_), Some(y_)) => array[[x_, y_]] * k,
+                _ => fill * k,
+            };
+        }
+        new_val
+    })
+}
+
+/// Convolves rows with kernel_x and then columns with kernel_y, both kernels have to be of odd length.
+pub fn convolve_separable(array: &Array2<f64>, kernel_x: &Array1<f64>, kernel_y: &Array1<f64>) -> Array2<f64> {
+    convolve_separable_bordered(array, kernel_x, kernel_y, &BorderMode::Clamp, 0.0)
+}
+
+/// Same as convolve_separable, fill is the value of constant borders for this channel.
+pub fn convolve_separable_bordered(array: &Array2<f64>, kernel_x: &Array1<f64>, kernel_y: &Array1<f64>, border: &BorderMode, fill: f64) -> Array2<f64> {
+    let (width, height) = array.dim();
+    let (radius_x, radius_y) = (kernel_x.len() / 2, kernel_y.len() / 2);
+    let (indices_x, indices_y) = (border_indices(width, radius_x, border), border_indices(height, radius_y, border));
+
+    let horizontal = par_from_fn(width, height, |x, y| kernel_x.iter()
+        .zip(&indices_x[x..])
+        .map(|(k, x_)| x_.map_or(fill, |x_| array[[x_, y]]) * k)
+        .sum());
+
+    // rows outside of a constant border are entirely made of the fill value
+    let fill_row = fill * kernel_x.sum();
+    par_from_fn(width, height, |x, y| kernel_y.iter()
+        .zip(&indices_y[y..])
+        .map(|(k, y_)| y_.map_or(fill_row, |y_| horizontal[[x, y_]]) * k)
+        .sum())
+}
+
+// fixed point scale of the running sums in box_mean
+const BOX_SUM_SCALE: f64 = 65536.0;
+
+/// Mean over a (2 * radius + 1) square window, computed with running sums in O(1) per pixel.
+pub fn box_mean(array: &Array2<f64>, radius: i32) -> Array2<f64> {
+    box_mean_bordered(array, radius, &BorderMode::Clamp, 0.0)
+}
+
+/// Same as box_mean, fill is the value of constant borders for this channel.
+pub fn box_mean_bordered(array: &Array2<f64>, radius: i32, border: &BorderMode, fill: f64) -> Array2<f64> {
+    let (width, height) = array.dim();
+    let radius = radius.max(0) as usize;
+    let area = ((2 * radius + 1) * (2 * radius + 1)) as f64;
+    // one more index on each side, the running sums look one position past the window
+    let (indices_x, indices_y) = (border_indices(width, radius + 1, border), border_indices(height, radius + 1, border));
+    // sums are kept in fixed point, so they are exact and don't depend on where along the line they started
+    let fixed = |v: f64| (v * BOX_SUM_SCALE).round() as i64;
+
+    let mut horizontal = Array2::<f64>::zeros((width, height));
+    par_for_each_line(&mut horizontal, Axis(0), |y, mut row| {
+        let value = |i: usize| fixed(indices_x[i].map_or(fill, |x_| array[[x_, y]]));
+        let mut sum: i64 = (1..2 * radius + 2).map(value).sum();
+        for x in 0..width {
+            row[x] = sum as f64;
+            sum += value(x + 2 * radius + 2) - value(x + 1);
+        }
+    });
+
+    // rows outside of a constant border sum up to the fill value times the window width
+    let fill_sum = fixed(fill) * (2 * radius + 1) as i64;
+    let mut res = Array2::<f64>::zeros((width, height));
+    par_for_each_line(&mut res, Axis(1), |x, mut column| {
+        let value = |j: usize| indices_y[j].map_or(fill_sum, |y_| horizontal[[x, y_]] as i64);
+        let mut sum: i64 = (1..2 * radius + 2).map(value).sum();
+        for y in 0..height {
+            column[y] = sum as f64 / BOX_SUM_SCALE / area;
+            sum += value(y + 2 * radius + 2) - value(y + 1);
+        }
+    });
+    res
+}
+
+/// Median over a (2 * radius + 1) square window for arrays holding whole values in 0..=255.
+/// Keeps a histogram of the window that slides along each row, <https://en.wikipedia.org/wiki/Median_filter>.
+pub fn median_filter(array: &Array2<f64>, radius: i32, border: &BorderMode, fill: f64) -> Array2<f64> {
+    let (width, height) = array.dim();
+    let radius = radius.max(0) as usize;
+    let diameter = 2 * radius + 1;
+    let half = diameter * diameter / 2;
+    let (indices_x, indices_y) = (border_indices(width, radius, border), border_indices(height, radius, border));
+    let value = |x: usize, y: usize| match (indices_x[x], indices_y[y]) {
+        (Some(x_), Some(y_)) => array[[x_, y_]] as u8 as usize,
+        _ => fill as u8 as usize,
+    };
+    let mut res = Array2::<f64>::zeros((width, height));
+
+    par_for_each_line(&mut res, Axis(0), |y, mut row| {
+        let mut histogram = [0usize; 256];
+        for i in 0..diameter {
+            for j in y..y + diameter {
+                histogram[value(i, j)] += 1;
+            }
+        }
+        // the median is the value where the count of smaller values first stays at most half of the window
+        let (mut median, mut below) = (0, 0);
+
+        for x in 0..width {
+            if x > 0 {
+                for j in y..y + diameter {
+                    let (old, new) = (value(x - 1, j), value(x + diameter - 1, j));
+                    histogram[old] -= 1;
+                    histogram[new] += 1;
+                    below -= (old < median) as usize;
+                    below += (new < median) as usize;
+                }
+            }
+            while below > half {
+                median -= 1;
+                below -= histogram[median];
+            }
+            while below + histogram[median] <= half {
+                below += histogram[median];
+                median += 1;
+            }
+            row[x] = median as f64;
+        }
+    });
+    res
+}
+
+/// Widths of n box filters that together approximate a gaussian, <http://blog.ivank.net/fastest-gaussian-blur.html>.
+pub fn gaussian_boxes(sigma: f64, n: usize) -> Vec<i32> {
+    let n_ = n as f64;
+    let ideal = (12.0 * sigma * sigma / n_ + 1.0).sqrt();
+    let lower = match ideal.floor() as i32 {
+        w if w % 2 == 0 => w - 1,
+        w => w,
+    }.max(1);
+    let upper = lower + 2;
+    let l = lower as f64;
+    let m = ((12.0 * sigma * sigma - n_ * l * l - 4.0 * n_ * l - 3.0 * n_) / (-4.0 * l - 4.0)).round() as usize;
+    (0..n).map(|i| if i < m { lower } else { upper }).collect()
+}
+
+/// Values above high are kept along with values above low that are 8-connected to them.
+pub fn hysteresis(array: &Array2<f64>, low: f64, high: f64) -> Array2<bool> {
+    let (width, height) = array.dim();
+    let mut res = Array2::<bool>::from_elem((width, height), false);
+    let mut stack = Vec::<(usize, usize)>::new();
+
+    for ((x, y), &val) in array.indexed_iter() {
+        if val > high {
+            res[[x, y]] = true;
+            stack.push((x, y));
+        }
+    }
+
+    while let Some((x, y)) = stack.pop() {
+        for i in -1..=1 {
+            for j in -1..=1 {
+                let (x_, y_) = (x as i32 + i, y as i32 + j);
+                if x_ < 0 || y_ < 0 || x_ >= width as i32 || y_ >= height as i32 {
+                    continue;
+                }
+                let (x_, y_) = (x_ as usize, y_ as usize);
+                if !res[[x_, y_]] && array[[x_, y_]] > low {
+                    res[[x_, y_]] = true;
+                    stack.push((x_, y_));
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Accepted border modes, as described in errors.
+pub const BORDER_MODES: &str = "clamp, reflect, mirror, wrap, constant or a hex color";
+
+/// How pixels outside of the image are resolved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BorderMode {
+    Clamp,       // aaa|abcd|ddd
+    Reflect,     // cba|abcd|dcb
+    Mirror,      // dcb|abcd|cba
+    Wrap,        // bcd|abcd|abc
+    Constant([u8; 3]),
+}
+
+impl std::str::FromStr for BorderMode {
+    type Err = String;
+
+    // a color stands for a constant border
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(BorderMode::Clamp),
+            "reflect" => Ok(BorderMode::Reflect),
+            "mirror" => Ok(BorderMode::Mirror),
+            "wrap" => Ok(BorderMode::Wrap),
+            "constant" => Ok(BorderMode::Constant([0, 0, 0])),
+            _ => parse_hex(s)
+                .map(BorderMode::Constant)
+                .map_err(|_| format!("{} is not a valid border mode", s)),
+        }
+    }
+}
+
+impl BorderMode {
+    /// Index inside 0..len, None for constant borders.
+    pub fn resolve(&self, i: i64, len: usize) -> Option<usize> {
+        let n = len as i64;
+        if (0..n).contains(&i) {
+            return Some(i as usize);
+        }
+        match self {
+            BorderMode::Clamp => Some(i.clamp(0, n - 1) as usize),
+            BorderMode::Wrap => Some(i.rem_euclid(n) as usize),
+            BorderMode::Reflect => {
+                let i = i.rem_euclid(2 * n);
+                Some((if i < n { i } else { 2 * n - 1 - i }) as usize)
+            },
+            BorderMode::Mirror if n == 1 => Some(0),
+            BorderMode::Mirror => {
+                let i = i.rem_euclid(2 * n - 2);
+                Some((if i < n { i } else { 2 * n - 2 - i }) as usize)
+            },
+            BorderMode::Constant(_) => None,
+        }
+    }
+
+    /// Value of channel c outside of the image for constant borders.
+    pub fn fill(&self, c: usize) -> f64 {
+        match self {
+            BorderMode::Constant(color) => color[c] as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// Value of channel c at a position that can be outside of the image.
+    pub fn pixel(&self, img: &Array3<u8>, x: i64, y: i64, c: usize) -> f64 {
+        let (width, height, _) = img.dim();
+        match (self.resolve(x, width), self.resolve(y, height), self) {
+            (Some(x), Some(y), _) => img[[x, y, c]] as f64,
+            (_, _, BorderMode::Constant(color)) => color[c] as f64,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// How colors are sampled between pixels.
+#[derive(Debug, Clone, Copy)]
+pub enum Interpolation {
+    Nearest,
+    Bilinear,
+    Bicubic,
+}
+
+impl std::str::FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Interpolation::Nearest),
+            "bilinear" => Ok(Interpolation::Bilinear),
+            "bicubic" => Ok(Interpolation::Bicubic),
+            _ => Err(format!("{} is not a valid interpolation", s)),
+        }
+    }
+}
+
+/// Interpolated value of a single channel at a fractional pixel position.
+pub fn sample_channel(img: &Array3<u8>, x: f64, y: f64, c: usize, interpolation: Interpolation, border: &BorderMode) -> f64 {
+    match interpolation {
+        Interpolation::Nearest => border.pixel(img, x.round() as i64, y.round() as i64, c),
+        Interpolation::Bilinear => {
+            let (x0, y0) = (x.floor(), y.floor());
+            let (tx, ty) = (x - x0, y - y0);
+            let (x0, y0) = (x0 as i64, y0 as i64);
+            let top = border.pixel(img, x0, y0, c) * (1.0 - tx) + border.pixel(img, x0 + 1, y0, c) * tx;
+            let bottom = border.pixel(img, x0, y0 + 1, c) * (1.0 - tx) + border.pixel(img, x0 + 1, y0 + 1, c) * tx;
+            top * (1.0 - ty) + bottom * ty
+        },
+        Interpolation::Bicubic => {
+            let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+            let kernel = ResampleKernel::CatmullRom;
+            let mut res = 0.0;
+            for i in -1..=2 {
+                let wx = kernel.weight(x - (x0 + i) as f64);
+                for j in -1..=2 {
+                    res += border.pixel(img, x0 + i, y0 + j, c) * wx * kernel.weight(y - (y0 + j) as f64);
+                }
+            }
+            res
+        },
+    }
+}
+
+/// Interpolated color at a fractional pixel position.
+pub fn sample(img: &Array3<u8>, x: f64, y: f64, interpolation: Interpolation, border: &BorderMode) -> [f64; 3] {
+    [
+        sample_channel(img, x, y, 0, interpolation, border),
+        sample_channel(img, x, y, 1, interpolation, border),
+        sample_channel(img, x, y, 2, interpolation, border),
+    ]
+}
+
+/// Solves a x = b with gaussian elimination and partial pivoting, None if the matrix is singular.
+pub fn solve(a: &Array2<f64>, b: &Array1<f64>) -> Option<Array1<f64>> {
+    let n = b.len();
+    let (mut a, mut b) = (a.clone(), b.clone());
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[[i, col]].abs().partial_cmp(&a[[j, col]].abs()).unwrap_or(Ordering::Equal))?;
+        if a[[pivot, col]].abs() < 1e-12 {
+            return None;
+        }
+        for k in 0..n {
+            a.swap([col, k], [pivot, k]);
+        }
+        b.swap(col, pivot);
+
+        for row in col + 1..n {
+            let factor = a[[row, col]] / a[[col, col]];
+            for k in col..n {
+                a[[row, k]] -= factor * a[[col, k]];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = Array1::<f64>::zeros(n);
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[[row, k]] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[[row, row]];
+    }
+    Some(x)
+}
+
+/// Filter used to resample images, e.g. when resizing them.
+#[derive(Debug, Clone, Copy)]
+pub enum ResampleKernel {
+    Nearest,
+    Bilinear,
+    CatmullRom,
+    Mitchell,
+    Lanczos3,
+}
+
+impl std::str::FromStr for ResampleKernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(ResampleKernel::Nearest),
+            "bilinear" => Ok(ResampleKernel::Bilinear),
+            "bicubic" | "catmullrom" => Ok(ResampleKernel::CatmullRom),
+            "mitchell" => Ok(ResampleKernel::Mitchell),
+            "lanczos" | "lanczos3" => Ok(ResampleKernel::Lanczos3),
+            _ => Err(format!("{} is not a valid resampling kernel", s)),
+        }
+    }
+}
+
+// Mitchell-Netravali family of cubic kernels, Catmull-Rom is b = 0, c = 0.5
+// https://www.cs.utexas.edu/~fussell/courses/cs384g-fall2013/lectures/mitchell/Mitchell.pdf
+fn cubic(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2) + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x.powi(3) + (6.0 * b + 30.0 * c) * x.powi(2) + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 { 1.0 } else { (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x) }
+}
+
+impl ResampleKernel {
+    /// Radius outside of which the kernel is zero.
+    pub fn support(&self) -> f64 {
+        match self {
+            ResampleKernel::Nearest => 0.5,
+            ResampleKernel::Bilinear => 1.0,
+            ResampleKernel::CatmullRom | ResampleKernel::Mitchell => 2.0,
+            ResampleKernel::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Kernel value at a distance of x pixels from its center.
+    pub fn weight(&self, x: f64) -> f64 {
+        match self {
+            ResampleKernel::Nearest => if (-0.5..0.5).contains(&x) { 1.0 } else { 0.0 },
+            ResampleKernel::Bilinear => (1.0 - x.abs()).max(0.0),
+            ResampleKernel::CatmullRom => cubic(x, 0.0, 0.5),
+            ResampleKernel::Mitchell => cubic(x, 1.0 / 3.0, 1.0 / 3.0),
+            ResampleKernel::Lanczos3 => if x.abs() < 3.0 { sinc(x) * sinc(x / 3.0) } else { 0.0 },
+        }
+    }
+}
+
+/// For every destination index, the first source index and the normalized weights of the source pixels.
+pub fn resample_contributions(src_len: usize, dst_len: usize, kernel: ResampleKernel) -> Vec<(usize, Vec<f64>)> {
+    let scale = src_len as f64 / dst_len as f64;
+    // when downscaling the kernel is stretched to cover every source pixel, which prevents aliasing
+    let filter_scale = match kernel {
+        ResampleKernel::Nearest => 1.0,
+        _ => scale.max(1.0),
+    };
+    let support = kernel.support() * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale - 0.5;
+            let start = ((center - support).floor() as i64).clamp(0, src_len as i64 - 1) as usize;
+            let end = ((center + support).ceil() as i64).clamp(0, src_len as i64 - 1) as usize;
+            let mut weights = (start..=end)
+                .map(|j| kernel.weight((j as f64 - center) / filter_scale))
+                .collect::<Vec<f64>>();
+            let sum = weights.iter().sum::<f64>();
+            if sum.abs() < f64::EPSILON {
+                // only happens at the very edges, falling back to the closest source pixel
+                let nearest = center.round().clamp(0.0, (src_len - 1) as f64) as usize;
+                return (nearest, vec![1.0]);
+            }
+            weights.iter_mut().for_each(|w| *w /= sum);
+            (start, weights)
+        })
+        .collect()
+}
+
+/// Separable resampling, rows first and columns second.
+pub fn resample(array: &Array2<f64>, width: usize, height: usize, kernel: ResampleKernel) -> Array2<f64> {
+    let (src_width, src_height) = array.dim();
+
+    let contributions_x = resample_contributions(src_width, width, kernel);
+    let horizontal = par_from_fn(width, src_height, |x, y| {
+        let (start, weights) = &contributions_x[x];
+        weights.iter().enumerate().map(|(i, w)| array[[start + i, y]] * w).sum()
+    });
+
+    let contributions_y = resample_contributions(src_height, height, kernel);
+    par_from_fn(width, height, |x, y| {
+        let (start, weights) = &contributions_y[y];
+        weights.iter().enumerate().map(|(i, w)| horizontal[[x, start + i]] * w).sum()
+    })
+}
+
+/// Horizontal and vertical image gradients, <https://en.wikipedia.org/wiki/Sobel_operator>.
+pub fn sobel(array: &Array2<f64>) -> (Array2<f64>, Array2<f64>) {
+    let derivative = Array1::from(vec![-1.0, 0.0, 1.0]);
+    let smoothing = Array1::from(vec![1.0, 2.0, 1.0]);
+    (
+        convolve_separable(array, &derivative, &smoothing),
+        convolve_separable(array, &smoothing, &derivative),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::ThreadPoolBuilder;
+    use crate::color::Palette;
+    use crate::filters::{Filter, Manipulate};
+    use crate::filters::bilateral::Bilateral;
+    use crate::filters::blur::{Blur, BlurMode};
+    use crate::filters::dither::{Dither, DitherMethod};
+    use crate::imgarray::noise_image;
+
+    // the parallel helpers split the work differently on every pool, the results must not depend on it
+    #[test]
+    fn results_ignore_thread_count() {
+        let img = noise_image(41, 33, 42);
+        let filters = || -> Vec<Filter> {
+            vec![
+                Filter::Blur(Blur::new(4, BlurMode::Gaussian)),
+                Filter::Blur(Blur::new(3, BlurMode::Median)),
+                Filter::Bilateral(Bilateral::new(3, 2.0, 30.0)),
+                Filter::Bilateral(Bilateral::new(3, 2.0, 30.0).with_modes(true, false)),
+                Filter::Dither(Dither::new(DitherMethod::FloydSteinberg, Palette::gray(2))),
+            ]
+        };
+        let render = |threads: usize| {
+            let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| filters().iter_mut().map(|filter| filter.apply(&img)).collect::<Vec<_>>())
+        };
+        for (single, multi) in render(1).iter().zip(render(4)) {
+            assert_eq!(single, &multi);
+        }
+    }
+}
@@ -1,13 +1,41 @@
-pub mod imgarray;
-pub mod filters;
-pub mod linalg;
-pub mod driver;
+mod driver;
 
 use crate::driver::{Context, get_user_input, driver};
 
 fn main() {
     let mut ctx = Context::default();
 
+    let args = std::env::args().collect::<Vec<String>>();
+    if let Some(i) = args.iter().position(|arg| arg == "--threads") {
+        match args.get(i + 1).map(|s| s.parse::<usize>()) {
+            Some(Ok(threads)) => {
+                if let Err(e) = ctx.set_threads(threads) {
+                    println!("Unable to start threads: {}", e);
+                    return;
+                }
+            },
+            _ => {
+                println!("Usage: magrustte [--threads <n>] [--profile] [--headless]");
+                return;
+            },
+        }
+    }
+
+    ctx.headless = args.iter().any(|arg| arg == "--headless");
+
+    // every render prints the profile of its filters
+    ctx.filters_composed.profiling = args.iter().any(|arg| arg == "--profile");
+
+    // Ctrl-C cancels the render in progress, at the prompt it still exits
+    let rendering = ctx.rendering.clone();
+    let cancel = move || match rendering.lock().unwrap().as_ref() {
+        Some(progress) => progress.cancel(),
+        None => std::process::exit(130),
+    };
+    if let Err(e) = ctrlc::set_handler(cancel) {
+        println!("Unable to handle Ctrl-C: {}", e);
+    }
+
     println!("Welcome to Magrustte!");
     println!("Type 'help' to see available commands.");
     while ctx.is_running {
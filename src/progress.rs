@@ -0,0 +1,160 @@
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+thread_local! {
+    // render in progress on this thread, set by Progress::run for as long as a filter is applied
+    static CURRENT: RefCell<Reporter> = RefCell::new(Reporter::default());
+}
+
+// called with the step being rendered, the fraction of its current pass that is done and the estimated time left
+type Report = dyn Fn(&str, f64, Option<Duration>) + Send + Sync;
+
+/// Progress of a render, reported to a callback, and whether it was cancelled.
+pub struct Progress {
+    report: Box<Report>,
+    cancelled: AtomicBool,
+    step: Mutex<(String, Instant)>,
+    lines_done: AtomicUsize,
+    lines_total: AtomicUsize,
+    // last reported percentage, so that the callback isn't called for every line
+    percent: AtomicUsize,
+}
+
+impl Progress {
+    pub fn new(report: impl Fn(&str, f64, Option<Duration>) + Send + Sync + 'static) -> Self {
+        Self {
+            report: Box::new(report),
+            cancelled: AtomicBool::new(false),
+            step: Mutex::new((String::new(), Instant::now())),
+            lines_done: AtomicUsize::new(0),
+            lines_total: AtomicUsize::new(0),
+            percent: AtomicUsize::new(0),
+        }
+    }
+
+    /// The filters stop early and the render returns None.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// A new step of the render, e.g. the next filter of a pipeline.
+    pub fn start(&self, step: &str) {
+        *self.step.lock().unwrap() = (step.to_string(), Instant::now());
+        self.start_pass(0);
+        (self.report)(step, 0.0, None);
+    }
+
+    // filters can go over the image a few times, e.g. once per channel, each pass is reported from 0 to 100%
+    fn start_pass(&self, lines: usize) {
+        self.step.lock().unwrap().1 = Instant::now();
+        self.lines_done.store(0, Ordering::Relaxed);
+        self.lines_total.store(lines, Ordering::Relaxed);
+        self.percent.store(0, Ordering::Relaxed);
+    }
+
+    fn line_done(&self) {
+        let done = self.lines_done.fetch_add(1, Ordering::Relaxed) + 1;
+        let total = self.lines_total.load(Ordering::Relaxed).max(done);
+        let percent = done * 100 / total;
+        if self.percent.swap(percent, Ordering::Relaxed) != percent {
+            let fraction = done as f64 / total as f64;
+            let (step, start) = self.step.lock().unwrap().clone();
+            let left = start.elapsed().mul_f64((1.0 - fraction) / fraction);
+            (self.report)(&step, fraction, Some(left));
+        }
+    }
+
+    // runs f with this as the render in progress of the current thread, which the filters applied in f report to
+    pub(crate) fn run<T>(self: &Arc<Self>, f: impl FnOnce() -> T) -> T {
+        Reporter { progress: Some(self.clone()), tiled: false }.run(f)
+    }
+}
+
+// what a filter reports to, taken from the current thread and moved into the closures of the parallel helpers,
+// as the threads of the pool don't see the render of the thread that started them
+// its methods are no-ops outside of a render
+#[derive(Clone, Default)]
+pub(crate) struct Reporter {
+    progress: Option<Arc<Progress>>,
+    // set for the filter applied to every tile, only the finished tiles are reported
+    tiled: bool,
+}
+
+impl Reporter {
+    pub(crate) fn current() -> Self {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    // the previous reporter of the thread is restored when f returns or panics
+    pub(crate) fn run<T>(self, f: impl FnOnce() -> T) -> T {
+        struct Restore(Reporter);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT.with(|current| *current.borrow_mut() = std::mem::take(&mut self.0));
+            }
+        }
+        let _restore = Restore(CURRENT.with(|current| current.replace(self)));
+        f()
+    }
+
+    pub(crate) fn start_pass(&self, lines: usize) {
+        match &self.progress {
+            Some(progress) if !self.tiled => progress.start_pass(lines),
+            _ => (),
+        }
+    }
+
+    pub(crate) fn line_done(&self) {
+        match &self.progress {
+            Some(progress) if !self.tiled => progress.line_done(),
+            _ => (),
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.progress.as_ref().is_some_and(|progress| progress.is_cancelled())
+    }
+
+    // the pass becomes the tiles, the returned reporter applies the filter to them without reporting its own passes
+    pub(crate) fn start_tiles(&self, tiles: usize) -> Reporter {
+        if let Some(progress) = &self.progress {
+            progress.start_pass(tiles);
+        }
+        Reporter { progress: self.progress.clone(), tiled: true }
+    }
+
+    pub(crate) fn tile_done(&self) {
+        if let Some(progress) = &self.progress {
+            progress.line_done();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_ends_with_its_scope() {
+        let first = Arc::new(Progress::new(|_, _, _| ()));
+        let second = Arc::new(Progress::new(|_, _, _| ()));
+        first.cancel();
+        // a render nested in another only sees its own progress
+        first.run(|| {
+            assert!(Reporter::current().is_cancelled());
+            second.run(|| assert!(!Reporter::current().is_cancelled()));
+            assert!(Reporter::current().is_cancelled());
+        });
+        assert!(Reporter::current().progress.is_none());
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| first.run(|| panic!("filter failed"))));
+        assert!(panicked.is_err());
+        assert!(Reporter::current().progress.is_none());
+    }
+}